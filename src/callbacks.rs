@@ -0,0 +1,52 @@
+use crate::messages::data_model::{
+    Datum, DatumPage, Descriptor, Event, EventDocument, EventPage, Resource, Start, Stop,
+    StreamDatum, StreamResource,
+};
+
+mod export;
+mod peaks;
+mod sequence;
+mod table;
+
+pub use export::{FileExport, SaveFormat};
+pub use peaks::PeakStats;
+pub use sequence::SequenceCheck;
+pub use table::LiveTable;
+
+/// Something that consumes the documents produced by a run
+///
+/// Each hook defaults to doing nothing so implementations only need to handle
+/// the documents they are interested in. Event pages are split into events
+/// unless `event_page` is overridden.
+pub trait Callback {
+    fn start(&mut self, _doc: &Start) {}
+    fn descriptor(&mut self, _doc: &Descriptor) {}
+    fn event(&mut self, _doc: &Event) {}
+    fn event_page(&mut self, doc: &EventPage) {
+        for event in doc.events() {
+            self.event(&event);
+        }
+    }
+    fn stop(&mut self, _doc: &Stop) {}
+    fn datum(&mut self, _doc: &Datum) {}
+    fn datum_page(&mut self, _doc: &DatumPage) {}
+    fn resource(&mut self, _doc: &Resource) {}
+    fn stream_resource(&mut self, _doc: &StreamResource) {}
+    fn stream_datum(&mut self, _doc: &StreamDatum) {}
+
+    /// Pass a document to the hook for its type
+    fn document(&mut self, doc: &EventDocument) {
+        match doc {
+            EventDocument::Start(doc) => self.start(doc),
+            EventDocument::Descriptor(doc) => self.descriptor(doc),
+            EventDocument::Event(doc) => self.event(doc),
+            EventDocument::EventPage(doc) => self.event_page(doc),
+            EventDocument::Stop(doc) => self.stop(doc),
+            EventDocument::Datum(doc) => self.datum(doc),
+            EventDocument::DatumPage(doc) => self.datum_page(doc),
+            EventDocument::Resource(doc) => self.resource(doc),
+            EventDocument::StreamResource(doc) => self.stream_resource(doc),
+            EventDocument::StreamDatum(doc) => self.stream_datum(doc),
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::Callback;
+use crate::error::{BcliError, Result};
+use crate::messages::data_model::{Descriptor, Event, Start, Stop};
+use crate::progress::Printer;
+
+/// Format used when saving the data from a run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SaveFormat {
+    /// Comma separated values
+    #[default]
+    Csv,
+    /// Tab separated values
+    Tsv,
+}
+
+impl SaveFormat {
+    fn delimiter(&self) -> char {
+        match self {
+            SaveFormat::Csv => ',',
+            SaveFormat::Tsv => '\t',
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            SaveFormat::Csv => "csv",
+            SaveFormat::Tsv => "tsv",
+        }
+    }
+}
+
+/// Writes the events of each stream of a run to its own file
+///
+/// Files are named `<scan_id>-<stream>.csv` (or using the run's uid if it has
+/// no scan ID), with a numbered suffix if the file already exists so that runs
+/// that reuse a scan ID don't overwrite each other. Each row is flushed as its
+/// event arrives so that data is not lost if the run is interrupted.
+pub struct FileExport {
+    printer: Printer,
+    dir: PathBuf,
+    format: SaveFormat,
+    /// Prefix for the files of each run that has started but not stopped
+    runs: HashMap<Uuid, String>,
+    streams: HashMap<Uuid, StreamFile>,
+}
+
+struct StreamFile {
+    path: PathBuf,
+    /// The run the stream is part of
+    run_start: Uuid,
+    keys: Vec<String>,
+    writer: BufWriter<File>,
+}
+
+impl FileExport {
+    /// Export to files in the given directory, creating it if required
+    pub fn new(printer: Printer, dir: &Path, format: SaveFormat) -> Result<Self> {
+        fs::create_dir_all(dir).map_err(|e| BcliError::Io(dir.to_owned(), e))?;
+        Ok(Self {
+            printer,
+            dir: dir.to_owned(),
+            format,
+            runs: HashMap::new(),
+            streams: HashMap::new(),
+        })
+    }
+
+    fn create(&self, run: &str, doc: &Descriptor) -> io::Result<StreamFile> {
+        let stream = doc.name.as_deref().unwrap_or("unknown");
+        let ext = self.format.extension();
+        let mut n = 1;
+        let (path, writer) = loop {
+            let path = match n {
+                1 => self.dir.join(format!("{run}-{stream}.{ext}")),
+                n => self.dir.join(format!("{run}-{stream}-{n}.{ext}")),
+            };
+            match File::create_new(&path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                file => break (path, file?),
+            }
+        };
+        let mut keys = doc.data_keys.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        let mut file = StreamFile {
+            writer: BufWriter::new(writer),
+            path,
+            run_start: doc.run_start,
+            keys,
+        };
+        let header = ["seq_num", "time"]
+            .into_iter()
+            .chain(file.keys.iter().map(String::as_str))
+            .map(String::from)
+            .collect::<Vec<_>>();
+        file.write_row(&header, self.format.delimiter())?;
+        Ok(file)
+    }
+
+    /// Close the files of any run that didn't stop
+    pub fn finish(&mut self) {
+        self.runs.clear();
+        for (_, file) in self.streams.drain() {
+            file.close(&self.printer, " (run did not stop)");
+        }
+    }
+
+    /// Report a failure to write a stream's file and stop writing to it
+    fn failed(&mut self, descriptor: Uuid, err: io::Error) {
+        if let Some(file) = self.streams.remove(&descriptor) {
+            self.printer.println(format_args!(
+                "Couldn't write to {}: {err}",
+                file.path.display()
+            ));
+        }
+    }
+}
+
+impl Callback for FileExport {
+    fn start(&mut self, doc: &Start) {
+        let prefix = match doc.scan_id {
+            Some(id) => id.to_string(),
+            None => doc.uid.to_string(),
+        };
+        self.runs.insert(doc.uid, prefix);
+    }
+
+    fn descriptor(&mut self, doc: &Descriptor) {
+        let run = self
+            .runs
+            .get(&doc.run_start)
+            .cloned()
+            .unwrap_or_else(|| doc.run_start.to_string());
+        match self.create(&run, doc) {
+            Ok(file) => {
+                self.streams.insert(doc.uid, file);
+            }
+            Err(e) => self.printer.println(format_args!(
+                "Couldn't create file for stream '{}': {e}",
+                doc.name.as_deref().unwrap_or_default()
+            )),
+        }
+    }
+
+    fn event(&mut self, doc: &Event) {
+        let delimiter = self.format.delimiter();
+        let Some(file) = self.streams.get_mut(&doc.descriptor) else {
+            return;
+        };
+        let row = [doc.seq_num.to_string(), doc.time.to_string()]
+            .into_iter()
+            .chain(file.keys.iter().map(|key| match doc.data.get(key) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+            }))
+            .collect::<Vec<_>>();
+        if let Err(e) = file.write_row(&row, delimiter) {
+            self.failed(doc.descriptor, e);
+        }
+    }
+
+    fn stop(&mut self, doc: &Stop) {
+        // Other runs may still be going if runs overlap
+        self.runs.remove(&doc.run_start);
+        let stopped = self
+            .streams
+            .extract_if(|_, file| file.run_start == doc.run_start)
+            .collect::<Vec<_>>();
+        for (_, file) in stopped {
+            file.close(&self.printer, "");
+        }
+    }
+}
+
+impl StreamFile {
+    /// Flush the file and report where it was saved
+    fn close(mut self, printer: &Printer, note: &str) {
+        match self.writer.flush() {
+            Ok(()) => printer.println(format_args!("Saved {}{note}", self.path.display())),
+            Err(e) => printer.println(format_args!(
+                "Couldn't write to {}: {e}",
+                self.path.display()
+            )),
+        }
+    }
+
+    /// Write a row and flush it to the file
+    fn write_row(&mut self, cells: &[String], delimiter: char) -> io::Result<()> {
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                write!(self.writer, "{delimiter}")?;
+            }
+            if cell.contains([delimiter, '"', '\n', '\r']) {
+                write!(self.writer, "\"{}\"", cell.replace('"', "\"\""))?;
+            } else {
+                write!(self.writer, "{cell}")?;
+            }
+        }
+        writeln!(self.writer)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use serde_json::{Value, json};
+    use uuid::Uuid;
+
+    use super::{FileExport, SaveFormat};
+    use crate::callbacks::Callback;
+    use crate::messages::data_model::{Descriptor, Event, Start, Stop};
+    use crate::progress::Printer;
+
+    /// An empty directory to export to
+    fn dir() -> PathBuf {
+        std::env::temp_dir().join(format!("bcli-export-{}", Uuid::new_v4()))
+    }
+
+    fn start(run: Uuid, scan_id: Option<u32>) -> Start {
+        serde_json::from_value(json!({"uid": run, "time": 0.0, "scan_id": scan_id}))
+            .expect("valid start")
+    }
+
+    fn descriptor(run: Uuid, uid: Uuid) -> Descriptor {
+        serde_json::from_value(json!({
+            "uid": uid,
+            "run_start": run,
+            "time": 0.0,
+            "name": "primary",
+            "data_keys": {
+                "det": {"dtype": "integer", "shape": [], "source": "PV:DET"},
+                "note": {"dtype": "string", "shape": [], "source": "PV:NOTE"},
+            },
+        }))
+        .expect("valid descriptor")
+    }
+
+    fn event(descriptor: Uuid, seq_num: u32, data: Value) -> Event {
+        serde_json::from_value(json!({
+            "uid": Uuid::new_v4(),
+            "time": 1.5,
+            "descriptor": descriptor,
+            "seq_num": seq_num,
+            "data": data,
+            "timestamps": {},
+        }))
+        .expect("valid event")
+    }
+
+    fn stop(run: Uuid) -> Stop {
+        serde_json::from_value(json!({
+            "uid": Uuid::new_v4(),
+            "run_start": run,
+            "time": 0.0,
+            "exit_status": "success",
+        }))
+        .expect("valid stop")
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).expect("file was written")
+    }
+
+    #[test]
+    fn quotes_cells() {
+        let dir = dir();
+        let mut export =
+            FileExport::new(Printer::default(), &dir, SaveFormat::Csv).expect("dir is created");
+        let (run, desc) = (Uuid::new_v4(), Uuid::new_v4());
+        export.start(&start(run, Some(7)));
+        export.descriptor(&descriptor(run, desc));
+        export.event(&event(desc, 1, json!({"det": 3, "note": "plain"})));
+        export.event(&event(desc, 2, json!({"det": 4, "note": "a, \"b\"\nc"})));
+        export.event(&event(desc, 3, json!({"note": null})));
+        export.stop(&stop(run));
+        assert_eq!(
+            read(&dir, "7-primary.csv"),
+            "seq_num,time,det,note\n\
+             1,1.5,3,plain\n\
+             2,1.5,4,\"a, \"\"b\"\"\nc\"\n\
+             3,1.5,,\n"
+        );
+        fs::remove_dir_all(dir).expect("removing test dir");
+    }
+
+    #[test]
+    fn names_files_by_run() {
+        let dir = dir();
+        let mut export =
+            FileExport::new(Printer::default(), &dir, SaveFormat::Tsv).expect("dir is created");
+        let runs = [
+            (Uuid::new_v4(), Some(7)),
+            (Uuid::new_v4(), Some(7)),
+            (Uuid::new_v4(), None),
+        ];
+        for (run, scan_id) in runs {
+            let desc = Uuid::new_v4();
+            export.start(&start(run, scan_id));
+            export.descriptor(&descriptor(run, desc));
+            export.event(&event(desc, 1, json!({"det": 1, "note": "a\tb"})));
+            export.stop(&stop(run));
+        }
+        let mut names = fs::read_dir(&dir)
+            .expect("dir exists")
+            .map(|entry| entry.expect("dir entry").file_name().into_string())
+            .collect::<Result<Vec<_>, _>>()
+            .expect("names are unicode");
+        names.sort();
+        let mut expected = vec![
+            "7-primary.tsv".to_owned(),
+            "7-primary-2.tsv".to_owned(),
+            format!("{}-primary.tsv", runs[2].0),
+        ];
+        expected.sort();
+        assert_eq!(names, expected);
+        assert_eq!(
+            read(&dir, "7-primary-2.tsv"),
+            "seq_num\ttime\tdet\tnote\n1\t1.5\t1\t\"a\tb\"\n"
+        );
+        fs::remove_dir_all(dir).expect("removing test dir");
+    }
+
+    #[test]
+    fn overlapping_runs() {
+        let dir = dir();
+        let mut export =
+            FileExport::new(Printer::default(), &dir, SaveFormat::Csv).expect("dir is created");
+        let (outer, outer_desc) = (Uuid::new_v4(), Uuid::new_v4());
+        let (inner, inner_desc) = (Uuid::new_v4(), Uuid::new_v4());
+        export.start(&start(outer, Some(1)));
+        export.descriptor(&descriptor(outer, outer_desc));
+        export.start(&start(inner, Some(2)));
+        export.descriptor(&descriptor(inner, inner_desc));
+        export.event(&event(inner_desc, 1, json!({"det": 1})));
+        export.stop(&stop(inner));
+        // Stopping the inner run leaves the outer run's file open
+        export.event(&event(outer_desc, 1, json!({"det": 2})));
+        export.stop(&stop(outer));
+        assert_eq!(
+            read(&dir, "1-primary.csv"),
+            "seq_num,time,det,note\n1,1.5,2,\n"
+        );
+        assert_eq!(
+            read(&dir, "2-primary.csv"),
+            "seq_num,time,det,note\n1,1.5,1,\n"
+        );
+        fs::remove_dir_all(dir).expect("removing test dir");
+    }
+}
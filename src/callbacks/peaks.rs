@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::Callback;
+use crate::messages::data_model::{DataType, Descriptor, Event, Start, Stop};
+use crate::output::Table;
+use crate::progress::Printer;
+
+const PRIMARY: &str = "primary";
+
+/// Statistics of each detector against the scanned motor, printed at the
+/// end of each run
+///
+/// Only scans with a motor (as listed in the start document) are analysed.
+pub struct PeakStats {
+    printer: Printer,
+    motors: Vec<String>,
+    primary: Option<Uuid>,
+    /// Data key of the motor readback, once known
+    motor: Option<Field>,
+    detectors: Vec<Field>,
+    x: Vec<f64>,
+    y: HashMap<String, Vec<f64>>,
+}
+
+struct Field {
+    key: String,
+    precision: Option<usize>,
+}
+
+/// Statistics of a single detector
+#[derive(Debug)]
+pub struct Stats {
+    /// Centre of mass after subtracting the minimum as a background
+    pub com: Option<f64>,
+    /// Position and value of the maximum reading
+    pub max: (f64, f64),
+    /// Position and value of the minimum reading
+    pub min: (f64, f64),
+    /// Full width at half maximum around the peak, if both edges were scanned
+    pub fwhm: Option<f64>,
+}
+
+impl PeakStats {
+    pub fn new(printer: Printer) -> Self {
+        Self {
+            printer,
+            motors: vec![],
+            primary: None,
+            motor: None,
+            detectors: vec![],
+            x: vec![],
+            y: HashMap::new(),
+        }
+    }
+
+    /// The statistics of each detector with readings, if there are any
+    fn table(&self, motor: &Field) -> Option<Table> {
+        let pos = |v: f64| fmt(v, motor.precision);
+        let rows = self
+            .detectors
+            .iter()
+            .filter_map(|det| {
+                let stats = Stats::compute(&self.x, self.y.get(&det.key)?)?;
+                Some([
+                    det.key.clone(),
+                    stats.com.map(pos).unwrap_or_default(),
+                    fmt(stats.max.1, det.precision),
+                    pos(stats.max.0),
+                    fmt(stats.min.1, det.precision),
+                    pos(stats.min.0),
+                    stats.fwhm.map(pos).unwrap_or_default(),
+                ])
+            })
+            .collect::<Vec<_>>();
+        (!rows.is_empty())
+            .then(|| Table::new(&["DETECTOR", "COM", "MAX", "AT", "MIN", "AT", "FWHM"]).rows(rows))
+    }
+}
+
+impl Callback for PeakStats {
+    fn start(&mut self, doc: &Start) {
+        self.motors = doc.motors.clone();
+        self.primary = None;
+        self.motor = None;
+        self.detectors.clear();
+        self.x.clear();
+        self.y.clear();
+    }
+
+    fn descriptor(&mut self, doc: &Descriptor) {
+        if doc.name.as_deref() != Some(PRIMARY) {
+            return;
+        }
+        // Motors are listed by device name but readings are keyed by signal
+        // name so look for the device's first signal if the name isn't a key
+        let Some(motor) = self.motors.iter().find_map(|motor| {
+            if doc.data_keys.contains_key(motor) {
+                return Some(motor.clone());
+            }
+            doc.object_keys
+                .get(motor)?
+                .as_array()?
+                .first()?
+                .as_str()
+                .map(String::from)
+        }) else {
+            return;
+        };
+        let precision = |key: &str| {
+            doc.data_keys
+                .get(key)
+                .and_then(|dk| dk.precision)
+                .map(|p| p.max(0) as usize)
+        };
+        let motor_keys = self
+            .motors
+            .iter()
+            .filter_map(|m| doc.object_keys.get(m)?.as_array().cloned())
+            .flatten()
+            .filter_map(|k| k.as_str().map(String::from))
+            .collect::<Vec<_>>();
+        let mut detectors = doc
+            .data_keys
+            .iter()
+            .filter(|(key, dk)| {
+                *key != &motor
+                    && !motor_keys.contains(key)
+                    && dk.shape.is_empty()
+                    && matches!(dk.dtype, DataType::Number | DataType::Integer)
+            })
+            .map(|(key, _)| Field {
+                key: key.clone(),
+                precision: precision(key),
+            })
+            .collect::<Vec<_>>();
+        detectors.sort_by(|a, b| a.key.cmp(&b.key));
+        self.motor = Some(Field {
+            precision: precision(&motor),
+            key: motor,
+        });
+        self.detectors = detectors;
+        self.primary = Some(doc.uid);
+    }
+
+    fn event(&mut self, doc: &Event) {
+        let Some(motor) = &self.motor else { return };
+        if self.primary != Some(doc.descriptor) {
+            return;
+        }
+        let Some(x) = doc.data.get(&motor.key).and_then(|x| x.as_f64()) else {
+            return;
+        };
+        self.x.push(x);
+        for det in &self.detectors {
+            let y = doc.data.get(&det.key).and_then(|y| y.as_f64());
+            self.y
+                .entry(det.key.clone())
+                .or_default()
+                .push(y.unwrap_or(f64::NAN));
+        }
+    }
+
+    fn stop(&mut self, _doc: &Stop) {
+        let Some(motor) = self.motor.take() else {
+            return;
+        };
+        // Skip the table entirely if nothing numeric was read against the motor
+        if let Some(table) = self.table(&motor) {
+            self.printer.println(format_args!(
+                "Peak statistics against {}\n{table}",
+                motor.key
+            ));
+        }
+    }
+}
+
+impl Stats {
+    /// Calculate the statistics of `y` against `x`, ignoring any points where
+    /// either is missing
+    pub fn compute(x: &[f64], y: &[f64]) -> Option<Self> {
+        let points = x
+            .iter()
+            .zip(y)
+            .map(|(&x, &y)| (x, y))
+            .filter(|(x, y)| x.is_finite() && y.is_finite())
+            .collect::<Vec<_>>();
+        let imax = (0..points.len()).max_by(|&a, &b| points[a].1.total_cmp(&points[b].1))?;
+        let imin = (0..points.len()).min_by(|&a, &b| points[a].1.total_cmp(&points[b].1))?;
+        let (max, min) = (points[imax], points[imin]);
+
+        let weights = points.iter().map(|(_, y)| y - min.1).sum::<f64>();
+        let com = (weights > 0.0)
+            .then(|| points.iter().map(|(x, y)| x * (y - min.1)).sum::<f64>() / weights);
+
+        // Walk out from the peak in both directions to find where the signal
+        // drops below half way between the min and max
+        let half = min.1 + (max.1 - min.1) / 2.0;
+        let left = crossing(&points, (0..=imax).rev(), half);
+        let right = crossing(&points, imax..points.len(), half);
+        let fwhm = left.zip(right).map(|(l, r)| (r - l).abs());
+
+        Some(Self {
+            com,
+            max,
+            min,
+            fwhm,
+        })
+    }
+}
+
+/// The interpolated x position at which the points visited by `indices` first
+/// drop below `y`
+fn crossing(points: &[(f64, f64)], indices: impl Iterator<Item = usize>, y: f64) -> Option<f64> {
+    let mut prev: Option<(f64, f64)> = None;
+    for i in indices {
+        let pt = points[i];
+        if pt.1 < y {
+            return prev.map(|prev| interpolate(prev, pt, y));
+        }
+        prev = Some(pt);
+    }
+    None
+}
+
+/// The x position at which the line between `a` and `b` reaches `y`
+fn interpolate(a: (f64, f64), b: (f64, f64), y: f64) -> f64 {
+    a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1)
+}
+
+fn fmt(value: f64, precision: Option<usize>) -> String {
+    let prec = match precision {
+        Some(prec) => prec,
+        None if value.fract() == 0.0 => 0,
+        None => 4,
+    };
+    // Round first so tiny negative values aren't printed as -0.000
+    let scale = 10f64.powi(prec as i32);
+    let value = (value * scale).round() / scale + 0.0;
+    format!("{value:.prec$}")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+    use uuid::Uuid;
+
+    use super::{PeakStats, Stats};
+    use crate::callbacks::Callback;
+    use crate::progress::Printer;
+
+    const RUN: &str = "aaaaaaaa-1111-4222-8333-444455556666";
+
+    /// Statistics for a scan of `x` reading the given data keys
+    fn scan(data_keys: Value, readings: &[Value]) -> PeakStats {
+        let desc = Uuid::new_v4();
+        let mut peaks = PeakStats::new(Printer::default());
+        peaks.start(
+            &serde_json::from_value(json!({"uid": RUN, "time": 0.0, "motors": ["x"]}))
+                .expect("valid start"),
+        );
+        peaks.descriptor(
+            &serde_json::from_value(json!({
+                "uid": desc,
+                "run_start": RUN,
+                "time": 0.0,
+                "name": "primary",
+                "data_keys": data_keys,
+            }))
+            .expect("valid descriptor"),
+        );
+        for (seq_num, data) in readings.iter().enumerate() {
+            peaks.event(
+                &serde_json::from_value(json!({
+                    "uid": Uuid::new_v4(),
+                    "time": 0.0,
+                    "descriptor": desc,
+                    "seq_num": seq_num + 1,
+                    "data": data,
+                    "timestamps": {},
+                }))
+                .expect("valid event"),
+            );
+        }
+        peaks
+    }
+
+    fn key(dtype: &str) -> Value {
+        json!({"dtype": dtype, "shape": [], "source": "sim"})
+    }
+
+    #[test]
+    fn no_table_without_numeric_fields() {
+        let peaks = scan(
+            json!({"x": key("number"), "label": key("string")}),
+            &[
+                json!({"x": 0.0, "label": "a"}),
+                json!({"x": 1.0, "label": "b"}),
+            ],
+        );
+        let motor = peaks.motor.as_ref().expect("motor found");
+        assert!(peaks.table(motor).is_none());
+    }
+
+    #[test]
+    fn table_of_numeric_fields() {
+        let peaks = scan(
+            json!({"x": key("number"), "det": key("integer"), "label": key("string")}),
+            &[
+                json!({"x": 0.0, "det": 1, "label": "a"}),
+                json!({"x": 1.0, "det": 3, "label": "b"}),
+            ],
+        );
+        let motor = peaks.motor.as_ref().expect("motor found");
+        let table = peaks.table(motor).expect("det is numeric").to_string();
+        assert_eq!(table.lines().count(), 2);
+        assert!(
+            table
+                .lines()
+                .nth(1)
+                .is_some_and(|row| row.starts_with("det "))
+        );
+    }
+
+    fn close(a: f64, b: f64, tol: f64) -> bool {
+        (a - b).abs() < tol
+    }
+
+    #[test]
+    fn gaussian() {
+        let x = (0..=100).map(|i| i as f64 / 10.0).collect::<Vec<_>>();
+        let y = x
+            .iter()
+            .map(|x| 10.0 * (-(x - 4.0f64).powi(2) / 2.0).exp())
+            .collect::<Vec<_>>();
+        let stats = Stats::compute(&x, &y).expect("points given");
+        assert_eq!(stats.max, (4.0, 10.0));
+        assert!(close(stats.com.expect("has weight"), 4.0, 1e-3));
+        // 2 * sqrt(2 ln 2) * sigma
+        assert!(close(stats.fwhm.expect("both edges scanned"), 2.3548, 1e-2));
+        assert_eq!(stats.min.0, 10.0);
+    }
+
+    #[test]
+    fn ramp_has_no_fwhm() {
+        let x = [0.0, 1.0, 2.0, 3.0, 4.0];
+        let y = [0.0, 1.0, 2.0, 3.0, 4.0];
+        let stats = Stats::compute(&x, &y).expect("points given");
+        assert_eq!(stats.max, (4.0, 4.0));
+        assert_eq!(stats.min, (0.0, 0.0));
+        assert_eq!(stats.fwhm, None);
+        assert!(close(stats.com.expect("has weight"), 3.0, 1e-9));
+    }
+
+    #[test]
+    fn constant_signal() {
+        let stats = Stats::compute(&[0.0, 1.0, 2.0], &[5.0; 3]).expect("points given");
+        assert_eq!(stats.max.1, 5.0);
+        assert_eq!(stats.min.1, 5.0);
+        assert_eq!(stats.com, None);
+        assert_eq!(stats.fwhm, None);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(Stats::compute(&[], &[]).is_none());
+        assert!(Stats::compute(&[1.0, 2.0], &[]).is_none());
+        assert!(Stats::compute(&[], &[1.0, 2.0]).is_none());
+    }
+
+    #[test]
+    fn mismatched_lengths_use_common_points() {
+        let stats = Stats::compute(&[0.0, 1.0, 2.0], &[1.0, 3.0]).expect("points given");
+        assert_eq!(stats.max, (1.0, 3.0));
+        assert_eq!(stats.min, (0.0, 1.0));
+        let stats = Stats::compute(&[0.0, 1.0], &[1.0, 3.0, 9.0]).expect("points given");
+        assert_eq!(stats.max, (1.0, 3.0));
+    }
+
+    #[test]
+    fn nan_points_are_ignored() {
+        let x = [0.0, 1.0, f64::NAN, 3.0, 4.0];
+        let y = [0.0, 2.0, 100.0, f64::NAN, 1.0];
+        let stats = Stats::compute(&x, &y).expect("points given");
+        assert_eq!(stats.max, (1.0, 2.0));
+        assert_eq!(stats.min, (0.0, 0.0));
+        assert!(close(stats.com.expect("has weight"), 2.0, 1e-9));
+        assert!(Stats::compute(&[f64::NAN], &[1.0]).is_none());
+    }
+}
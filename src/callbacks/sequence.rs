@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use super::Callback;
+use crate::messages::data_model::{Descriptor, Event, EventDocument, Start, Stop};
+use crate::progress::Printer;
+
+/// Warns about documents that appear to have been dropped by the event bus
+///
+/// Gaps in the sequence numbers of each stream, events from streams with no
+/// descriptor and streams with fewer events than reported by the stop
+/// document are all reported.
+///
+/// The event bus may deliver a document more than once, so documents should be
+/// checked with [`SequenceCheck::is_duplicate`] before being handled.
+pub struct SequenceCheck {
+    printer: Printer,
+    run: Option<Uuid>,
+    /// The last run a start document was received for
+    started: Option<Uuid>,
+    /// The last run a stop document was received for
+    stopped: Option<Uuid>,
+    streams: HashMap<Uuid, Stream>,
+    /// Descriptors we've received events for without seeing the descriptor
+    unknown: HashSet<Uuid>,
+    /// Whether monitoring started partway through a run, so the documents from
+    /// before it started are missing because they were never subscribed to
+    attached: bool,
+}
+
+struct Stream {
+    name: String,
+    last: u32,
+    count: u32,
+    /// UIDs of the events received so far
+    seen: HashSet<Uuid>,
+}
+
+impl SequenceCheck {
+    pub fn new(printer: Printer) -> Self {
+        Self {
+            printer,
+            run: None,
+            started: None,
+            stopped: None,
+            streams: HashMap::new(),
+            unknown: HashSet::new(),
+            attached: false,
+        }
+    }
+
+    /// Expect the start of the current run to be missing, until the next run
+    /// starts
+    pub fn attach(&mut self) {
+        self.attached = true;
+    }
+
+    /// Whether a document is a repeat of one that has already been received
+    pub fn is_duplicate(&self, doc: &EventDocument) -> bool {
+        let seen = |descriptor: &Uuid, uid: &Uuid| {
+            self.streams
+                .get(descriptor)
+                .is_some_and(|stream| stream.seen.contains(uid))
+        };
+        match doc {
+            EventDocument::Start(start) => self.started == Some(start.uid),
+            EventDocument::Descriptor(desc) => self.streams.contains_key(&desc.uid),
+            EventDocument::Event(event) => seen(&event.descriptor, &event.uid),
+            EventDocument::EventPage(page) => {
+                !page.uid.is_empty() && page.uid.iter().all(|uid| seen(&page.descriptor, uid))
+            }
+            EventDocument::Stop(stop) => self.stopped == Some(stop.run_start),
+            _ => false,
+        }
+    }
+}
+
+impl Stream {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            last: 0,
+            count: 0,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Record an event, returning a warning if it isn't the next one expected
+    fn record(&mut self, doc: &Event) -> Option<String> {
+        let expected = self.last + 1;
+        let warning = if doc.seq_num > expected {
+            let missed = match doc.seq_num - expected {
+                1 => format!("event {expected}"),
+                _ => format!("events {expected}-{}", doc.seq_num - 1),
+            };
+            Some(format!(
+                "Warning: missed {missed} from stream '{}'",
+                self.name
+            ))
+        } else if doc.seq_num < expected {
+            Some(format!(
+                "Warning: event {} from stream '{}' received out of order",
+                doc.seq_num, self.name
+            ))
+        } else {
+            None
+        };
+        self.last = self.last.max(doc.seq_num);
+        self.count += 1;
+        self.seen.insert(doc.uid);
+        warning
+    }
+}
+
+impl Callback for SequenceCheck {
+    fn start(&mut self, doc: &Start) {
+        self.run = Some(doc.uid);
+        self.started = Some(doc.uid);
+        self.streams.clear();
+        self.unknown.clear();
+        self.attached = false;
+    }
+
+    fn descriptor(&mut self, doc: &Descriptor) {
+        if self.run != Some(doc.run_start) && !self.attached {
+            self.printer.eprintln(format_args!(
+                "Warning: missed start document for run {}",
+                doc.run_start
+            ));
+            self.run = Some(doc.run_start);
+        }
+        self.streams
+            .insert(doc.uid, Stream::new(doc.name.clone().unwrap_or_default()));
+    }
+
+    fn event(&mut self, doc: &Event) {
+        let Some(stream) = self.streams.get_mut(&doc.descriptor) else {
+            if self.attached {
+                if self.unknown.is_empty() {
+                    self.printer.println(
+                        "Attached partway through a run - its data will be shown from the next run",
+                    );
+                }
+                self.unknown.insert(doc.descriptor);
+            } else if self.unknown.insert(doc.descriptor) {
+                self.printer.eprintln(format_args!(
+                    "Warning: missed descriptor {} - its events will not be shown",
+                    doc.descriptor
+                ));
+            }
+            return;
+        };
+        if let Some(warning) = stream.record(doc) {
+            self.printer.eprintln(warning);
+        }
+    }
+
+    fn stop(&mut self, doc: &Stop) {
+        for stream in self.streams.values() {
+            let Some(&expected) = doc.num_events.get(&stream.name) else {
+                continue;
+            };
+            if (stream.count as i32) < expected {
+                self.printer.eprintln(format_args!(
+                    "Warning: received {} of {expected} events from stream '{}'",
+                    stream.count, stream.name
+                ));
+            }
+        }
+        self.run = None;
+        self.stopped = Some(doc.run_start);
+        self.streams.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::SequenceCheck;
+    use crate::callbacks::Callback;
+    use crate::messages::data_model::{Descriptor, Event, EventDocument, Start};
+    use crate::progress::Printer;
+
+    const RUN: &str = "aaaaaaaa-1111-4222-8333-444455556666";
+
+    fn start() -> Start {
+        serde_json::from_value(json!({"uid": RUN, "time": 0.0})).expect("valid start")
+    }
+
+    fn descriptor(uid: Uuid) -> Descriptor {
+        serde_json::from_value(json!({
+            "uid": uid,
+            "run_start": RUN,
+            "time": 0.0,
+            "name": "primary",
+            "data_keys": {},
+        }))
+        .expect("valid descriptor")
+    }
+
+    fn event(descriptor: Uuid, seq_num: u32) -> Event {
+        serde_json::from_value(json!({
+            "uid": Uuid::new_v4(),
+            "time": 0.0,
+            "descriptor": descriptor,
+            "seq_num": seq_num,
+            "data": {},
+            "timestamps": {},
+        }))
+        .expect("valid event")
+    }
+
+    /// A check for a run that has started, with one stream described
+    fn started() -> (SequenceCheck, Uuid) {
+        let mut check = SequenceCheck::new(Printer::default());
+        let desc = Uuid::new_v4();
+        check.start(&start());
+        check.descriptor(&descriptor(desc));
+        (check, desc)
+    }
+
+    /// The warning for each event in turn, recorded against a stream
+    fn warnings(check: &mut SequenceCheck, desc: Uuid, seq_nums: &[u32]) -> Vec<Option<String>> {
+        let stream = check.streams.get_mut(&desc).expect("stream is described");
+        seq_nums
+            .iter()
+            .map(|seq_num| stream.record(&event(desc, *seq_num)))
+            .collect()
+    }
+
+    #[test]
+    fn in_order() {
+        let (mut check, desc) = started();
+        assert_eq!(warnings(&mut check, desc, &[1, 2, 3]), [None, None, None]);
+    }
+
+    #[test]
+    fn gap() {
+        let (mut check, desc) = started();
+        assert_eq!(
+            warnings(&mut check, desc, &[1, 3, 4, 7]),
+            [
+                None,
+                Some("Warning: missed event 2 from stream 'primary'".into()),
+                None,
+                Some("Warning: missed events 5-6 from stream 'primary'".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reordered() {
+        let (mut check, desc) = started();
+        assert_eq!(
+            warnings(&mut check, desc, &[1, 3, 2, 4]),
+            [
+                None,
+                Some("Warning: missed event 2 from stream 'primary'".into()),
+                Some("Warning: event 2 from stream 'primary' received out of order".into()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate() {
+        let (mut check, desc) = started();
+        let first = event(desc, 1);
+        check.event(&first);
+        assert!(check.is_duplicate(&EventDocument::Event(first.clone())));
+        // Another event with the same sequence number is not a redelivery
+        assert!(!check.is_duplicate(&EventDocument::Event(event(desc, 1))));
+        assert!(check.is_duplicate(&EventDocument::Descriptor(descriptor(desc))));
+        assert!(check.is_duplicate(&EventDocument::Start(start())));
+        assert_eq!(check.streams[&desc].count, 1);
+    }
+
+    #[test]
+    fn new_descriptor_resets_sequence() {
+        let (mut check, first) = started();
+        assert_eq!(warnings(&mut check, first, &[1, 2]), [None, None]);
+        let second = Uuid::new_v4();
+        check.descriptor(&descriptor(second));
+        assert_eq!(warnings(&mut check, second, &[1, 2]), [None, None]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, Local};
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::Callback;
+use crate::messages::data_model::{DataKey, DataType, Descriptor, Event, Start, Stop};
+use crate::progress::Printer;
+
+/// Name of the stream shown in the table
+const PRIMARY: &str = "primary";
+const MIN_WIDTH: usize = 10;
+
+/// Table of readings printed as a scan progresses, similar to bluesky's
+/// BestEffortCallback
+///
+/// Columns are taken from the scalar data keys of the primary stream and a
+/// row is printed for each event in that stream.
+pub struct LiveTable {
+    printer: Printer,
+    /// The run whose header has been printed
+    scan: Option<Scan>,
+    primary: Option<Uuid>,
+    columns: Vec<Column>,
+}
+
+/// Details from the start document needed for the footer
+struct Scan {
+    uid: Uuid,
+    scan_id: Option<u32>,
+    plan_name: Option<String>,
+}
+
+struct Column {
+    key: String,
+    label: String,
+    width: usize,
+    data_key: DataKey,
+}
+
+impl LiveTable {
+    pub fn new(printer: Printer) -> Self {
+        Self {
+            printer,
+            scan: None,
+            primary: None,
+            columns: vec![],
+        }
+    }
+
+    fn border(&self) -> String {
+        let mut line = format!("+{}+{}+", "-".repeat(MIN_WIDTH + 2), "-".repeat(14));
+        for col in &self.columns {
+            line.push_str(&"-".repeat(col.width + 2));
+            line.push('+');
+        }
+        line
+    }
+
+    fn row<'a>(&self, seq_num: &str, time: &str, cells: impl Iterator<Item = &'a str>) -> String {
+        let mut line = format!("| {seq_num:>MIN_WIDTH$} | {time:>12} |");
+        for (col, cell) in self.columns.iter().zip(cells) {
+            let cell = truncate(cell, col.width);
+            line.push_str(&format!(" {cell:>width$} |", width = col.width));
+        }
+        line
+    }
+}
+
+impl Callback for LiveTable {
+    fn start(&mut self, doc: &Start) {
+        let scan_id = doc.scan_id.map(|id| id.to_string());
+        self.printer.println(format_args!(
+            "\nTransient Scan ID: {}     Time: {}\nPersistent Unique Scan ID: '{}'",
+            scan_id.as_deref().unwrap_or("-"),
+            local_time(doc.time).format("%Y-%m-%d %H:%M:%S"),
+            doc.uid,
+        ));
+        self.scan = Some(Scan {
+            uid: doc.uid,
+            scan_id: doc.scan_id,
+            plan_name: doc.plan_name.clone(),
+        });
+        self.primary = None;
+        self.columns.clear();
+    }
+
+    fn descriptor(&mut self, doc: &Descriptor) {
+        if doc.name.as_deref() != Some(PRIMARY) {
+            return;
+        }
+        let mut keys = doc
+            .data_keys
+            .iter()
+            .filter(|(_, dk)| dk.shape.is_empty() && !matches!(dk.dtype, DataType::Array))
+            .collect::<Vec<_>>();
+        keys.sort_by_key(|(key, _)| key.as_str());
+        self.columns = keys.into_iter().map(|(k, dk)| Column::new(k, dk)).collect();
+        self.primary = Some(doc.uid);
+
+        let border = self.border();
+        let header = self.row(
+            "seq_num",
+            "time",
+            self.columns.iter().map(|c| c.label.as_str()),
+        );
+        self.printer
+            .println(format_args!("{border}\n{header}\n{border}"));
+    }
+
+    fn event(&mut self, doc: &Event) {
+        if self.primary != Some(doc.descriptor) {
+            return;
+        }
+        let cells = self
+            .columns
+            .iter()
+            .map(|col| col.format(doc.data.get(&col.key)))
+            .collect::<Vec<_>>();
+        let time = local_time(doc.time).format("%H:%M:%S%.3f").to_string();
+        self.printer.println(self.row(
+            &doc.seq_num.to_string(),
+            &time,
+            cells.iter().map(String::as_str),
+        ));
+    }
+
+    fn stop(&mut self, doc: &Stop) {
+        if self.primary.take().is_some() {
+            self.printer.println(self.border());
+        }
+        // There is no header to match when attaching after the run started
+        let Some(scan) = self.scan.take_if(|scan| scan.uid == doc.run_start) else {
+            return;
+        };
+        self.printer.println(format_args!(
+            "{} ['{}'] (scan num: {}) {}\n",
+            scan.plan_name.as_deref().unwrap_or("plan"),
+            doc.run_start,
+            scan.scan_id
+                .map(|id| id.to_string())
+                .as_deref()
+                .unwrap_or("-"),
+            doc.exit_status,
+        ));
+    }
+}
+
+impl Column {
+    fn new(key: &str, dk: &DataKey) -> Self {
+        let label = match dk.units() {
+            Some(units) => format!("{key} ({units})"),
+            None => key.to_owned(),
+        };
+        Self {
+            key: key.to_owned(),
+            width: label.chars().count().max(MIN_WIDTH),
+            label,
+            data_key: dk.clone(),
+        }
+    }
+
+    fn format(&self, value: Option<&Value>) -> String {
+        match value {
+            None | Some(Value::Null) => String::new(),
+            Some(value) => self.data_key.format(value),
+        }
+    }
+}
+
+/// Shorten a cell to fit its column, marking where it was cut
+fn truncate(cell: &str, width: usize) -> Cow<'_, str> {
+    match cell.chars().count() > width {
+        true => Cow::Owned(cell.chars().take(width - 1).chain(['…']).collect()),
+        false => Cow::Borrowed(cell),
+    }
+}
+
+fn local_time(timestamp: f64) -> DateTime<Local> {
+    DateTime::from_timestamp_micros((timestamp * 1e6) as i64)
+        .unwrap_or_default()
+        .with_timezone(&Local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::truncate;
+
+    #[test]
+    fn truncates_to_width() {
+        assert_eq!(truncate("1.2345", 10), "1.2345");
+        assert_eq!(truncate("0123456789", 10), "0123456789");
+        assert_eq!(truncate("0123456789a", 10), "012345678…");
+        assert_eq!(truncate("ααααααααααα", 10), "ααααααααα…");
+    }
+}
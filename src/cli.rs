@@ -1,13 +1,110 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use clap_complete::engine::ArgValueCompleter;
+use reqwest::Url;
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{Map, Value};
+use uuid::Uuid;
 
-use crate::entities::SourceInfo;
+use crate::callbacks::{FileExport, SaveFormat};
+use crate::complete::{self, Shell};
+use crate::config::{EventBus, MqttAddress, Qos, StompAddress};
+use crate::entities::{SourceInfo, TaskId};
+use crate::error::Result;
+use crate::events::Speed;
+use crate::filter::{DocKind, MessageKind};
+use crate::output::OutputFormat;
+use crate::params;
+use crate::progress::Printer;
 
 #[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+    /// Format used to print results
+    #[clap(
+        short,
+        long,
+        global = true,
+        value_enum,
+        default_value_t,
+        env = "BCLI_OUTPUT"
+    )]
+    pub output: OutputFormat,
+    #[command(subcommand)]
+    pub command: CliArgs,
+}
+
+/// Options controlling which blueapi server to connect to
+#[derive(Debug, Args)]
+pub struct ConnectionArgs {
+    /// Named profile from the config file to use
+    #[clap(short, long, global = true, env = "BLUEAPI_PROFILE")]
+    pub profile: Option<String>,
+    /// Path to the config file [default: ~/.config/bcli/config.toml]
+    #[clap(long, global = true, env = "BLUEAPI_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// URL of the blueapi server, overriding the profile
+    #[clap(long, global = true, env = "BLUEAPI_URL")]
+    pub url: Option<Url>,
+    /// Kind of broker to receive events from, overriding the profile [default: mqtt]
+    #[clap(long, global = true, value_enum, env = "BLUEAPI_EVENT_BUS")]
+    pub event_bus: Option<EventBus>,
+    /// Address (host[:port]) of the MQTT broker, overriding the profile
+    #[clap(long, global = true, env = "BLUEAPI_MQTT")]
+    pub mqtt: Option<MqttAddress>,
+    /// MQTT quality of service to subscribe with, overriding the profile [default: 1]
+    #[clap(long, global = true, value_enum, env = "BLUEAPI_MQTT_QOS")]
+    pub qos: Option<Qos>,
+    /// Username to connect to the MQTT broker with, overriding the profile.
+    /// The password is read from BLUEAPI_MQTT_PASSWORD or the keyring
+    #[clap(long, global = true, env = "BLUEAPI_MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+    /// Connect to the MQTT broker using TLS
+    #[clap(long, global = true, env = "BLUEAPI_MQTT_TLS")]
+    pub mqtt_tls: bool,
+    /// CA bundle (PEM) to verify the MQTT broker with, implies --mqtt-tls
+    #[clap(long, global = true, env = "BLUEAPI_MQTT_CA")]
+    pub mqtt_ca: Option<PathBuf>,
+    /// Client certificate (PEM) to authenticate with the MQTT broker
+    #[clap(long, global = true, env = "BLUEAPI_MQTT_CERT")]
+    pub mqtt_cert: Option<PathBuf>,
+    /// Private key (PEM) for the client certificate
+    #[clap(long, global = true, env = "BLUEAPI_MQTT_KEY")]
+    pub mqtt_key: Option<PathBuf>,
+    /// Seconds between pings to the MQTT broker when no events are being
+    /// received (0 to disable), overriding the profile
+    #[clap(long, global = true, env = "BLUEAPI_MQTT_KEEP_ALIVE")]
+    pub mqtt_keep_alive: Option<u64>,
+    /// Address (host[:port]) of the STOMP broker, overriding the profile
+    #[clap(long, global = true, env = "BLUEAPI_STOMP")]
+    pub stomp: Option<StompAddress>,
+    /// Login for the STOMP broker, overriding the profile.
+    /// The passcode is read from BLUEAPI_STOMP_PASSWORD or the keyring
+    #[clap(long, global = true, env = "BLUEAPI_STOMP_USERNAME")]
+    pub stomp_username: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
 pub enum CliArgs {
     /// Run a plan
     Run(RunArgs),
+    /// Manage the tasks known to the worker
+    #[command(subcommand)]
+    Tasks(TaskCommand),
+    /// Follow the progress of a task that is already running
+    Attach {
+        /// The task to follow [default: the worker's active task]
+        #[arg(add = ArgValueCompleter::new(complete::tasks))]
+        task_id: Option<TaskId>,
+        #[command(flatten)]
+        save: SaveArgs,
+        /// Print worker events in full instead of on one line each
+        #[clap(short, long)]
+        verbose: bool,
+    },
     /// Pause the current task
     Pause {
         /// Defer the pause until the next checkpoint
@@ -23,11 +120,13 @@ pub enum CliArgs {
     /// List available devices
     Devices {
         /// Show information for a specific devices instead of listing all
+        #[arg(add = ArgValueCompleter::new(complete::devices))]
         name: Option<String>,
     },
     /// List available plans
     Plans {
         /// Show information for a specific plan instead of listing all
+        #[arg(add = ArgValueCompleter::new(complete::plans))]
         name: Option<String>,
     },
     /// Inspect or restart the environment
@@ -42,38 +141,227 @@ pub enum CliArgs {
     /// Retrieve the installed packages and their sources
     GetPythonEnv(PackageFilter),
     /// Print the current state of the worker
-    State,
+    State {
+        /// Keep running, printing each change of state along with the task
+        /// being run and any errors or warnings
+        #[clap(short, long)]
+        watch: bool,
+        /// Seconds between checks of the state if the event bus can't be used
+        #[clap(long, requires = "watch", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Wait until the worker, environment or a task reaches a condition
+    ///
+    /// Exits with an error if the condition isn't reached before the timeout,
+    /// if the worker panics or, when waiting for a task, if the task fails.
+    Wait {
+        #[command(subcommand)]
+        condition: WaitCondition,
+        /// Seconds to wait before giving up [default: wait forever]
+        #[clap(short, long, global = true)]
+        timeout: Option<u64>,
+    },
     /// Listen to events output by blueapi
-    Listen,
+    Listen {
+        #[command(flatten)]
+        filter: ListenFilter,
+        #[command(flatten)]
+        save: SaveArgs,
+        /// Record every message received to a file for later replay
+        #[clap(long)]
+        record: Option<PathBuf>,
+        /// Print messages in full instead of on one line each
+        #[clap(short, long)]
+        verbose: bool,
+    },
+    /// Replay events recorded by `listen --record` as if they were from a live run
+    Replay {
+        /// The recording to replay
+        file: PathBuf,
+        /// Speed up (or slow down) the replay, eg 10x. 0 replays without any delay
+        #[clap(long, default_value = "1x")]
+        speed: Speed,
+        #[command(flatten)]
+        save: SaveArgs,
+        /// Print worker events in full instead of on one line each
+        #[clap(short, long)]
+        verbose: bool,
+    },
+    /// Start an interactive session that runs commands over a single
+    /// connection to the server and event bus
+    Shell,
+    /// Print a script that enables tab completion in a shell
+    ///
+    /// Plan names, device names and task IDs are completed using the server
+    /// (and cached briefly). To enable completion add the following to your
+    /// shell's startup file:
+    ///
+    ///   bash: source <(bcli completions bash)
+    ///   zsh:  source <(bcli completions zsh)
+    ///   fish: bcli completions fish | source
+    Completions { shell: Shell },
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Subcommand)]
+pub enum TaskCommand {
+    /// List pending, running and finished tasks
+    List,
+    /// Show the parameters, errors and status of a task
+    Show {
+        #[arg(add = ArgValueCompleter::new(complete::tasks))]
+        task_id: TaskId,
+    },
+    /// Remove a pending task
+    Delete {
+        #[arg(add = ArgValueCompleter::new(complete::tasks))]
+        task_id: TaskId,
+    },
+    /// Start running a task that was created earlier
+    Start {
+        #[arg(add = ArgValueCompleter::new(complete::tasks))]
+        task_id: TaskId,
+        /// Return as soon as the task has started instead of waiting for it to complete
+        #[clap(short, long)]
+        background: bool,
+        #[command(flatten)]
+        save: SaveArgs,
+        /// Print worker events in full instead of on one line each
+        #[clap(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WaitCondition {
+    /// Wait for the worker to be idle
+    Idle,
+    /// Wait for the worker to be paused
+    Paused,
+    /// Wait for the environment to finish loading
+    EnvReady,
+    /// Wait for a task to complete
+    Task {
+        #[arg(add = ArgValueCompleter::new(complete::tasks))]
+        task_id: TaskId,
+    },
+}
+
+#[derive(Debug, Args)]
+#[command(disable_help_flag = true)]
 pub struct RunArgs {
     /// The name of the plan to run
-    name: String,
+    #[clap(required_unless_present = "help", add = ArgValueCompleter::new(complete::plans))]
+    name: Option<String>,
     /// The instrument session with which this plan should be associated
-    #[clap(short, long, env = "BLUEAPI_INSTRUMENT_SESSION")]
-    instrument_session: String,
-    /// Parameters to pass to the plan in JSON format
-    params: Option<String>,
+    #[clap(
+        short,
+        long,
+        env = "BLUEAPI_INSTRUMENT_SESSION",
+        required_unless_present = "help"
+    )]
+    instrument_session: Option<String>,
+    /// Parameters to pass to the plan as JSON or a python literal, files to
+    /// read them from (`@params.json`, `@params.yaml` or `-` for stdin) and/or
+    /// flags for individual parameters (see `bcli run <NAME> --help`)
+    ///
+    /// Later sources override earlier ones and flags override both. Flags must
+    /// come after any other options.
+    #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+    params: Vec<String>,
+    /// Don't check the parameters against the plan's schema before submitting
+    #[clap(long)]
+    pub no_validate: bool,
     /// Run the plan in the foreground blocking until the plan is complete
     #[clap(short, long)]
     foreground: bool,
     /// Run the plan in the background returning before the plan is complete
     #[clap(short, long, overrides_with = "foreground")]
     _background: bool,
+    #[command(flatten)]
+    pub save: SaveArgs,
+    /// Print worker events in full instead of on one line each
+    #[clap(short, long)]
+    pub verbose: bool,
+    /// Print help, including the parameters of the plan if one is given
+    #[clap(short, long)]
+    help: bool,
 }
 
-impl RunArgs {
-    pub fn name(&self) -> &str {
-        self.name.as_str()
-    }
-    pub fn parameters(&self) -> Result<Option<Value>, ()> {
-        self.params
+/// Options choosing which messages `listen` shows
+///
+/// Messages must match every option given. The document, run and scan options
+/// only match documents so exclude progress and worker events.
+#[derive(Debug, Args)]
+pub struct ListenFilter {
+    /// Only show messages for this task
+    #[clap(long, add = ArgValueCompleter::new(complete::tasks))]
+    pub task: Option<TaskId>,
+    /// Only show one kind of message
+    #[clap(long, value_enum)]
+    pub kind: Option<MessageKind>,
+    /// Only show these kinds of document (comma separated)
+    #[clap(long, value_enum, value_delimiter = ',')]
+    pub doc: Vec<DocKind>,
+    /// Only show documents from the run with this start document UID
+    #[clap(long)]
+    pub run: Option<Uuid>,
+    /// Only show documents from runs with this scan ID
+    #[clap(long)]
+    pub scan_id: Option<u32>,
+}
+
+#[derive(Debug, Args)]
+pub struct SaveArgs {
+    /// Directory to save the data from each stream of a run to
+    #[clap(long)]
+    pub save: Option<PathBuf>,
+    /// Format of the saved data
+    #[clap(long, value_enum, default_value_t, requires = "save")]
+    pub save_format: SaveFormat,
+}
+
+impl SaveArgs {
+    /// Create a callback to save run data if saving was requested
+    pub fn exporter(&self, printer: &Printer) -> Result<Option<FileExport>> {
+        self.save
             .as_deref()
-            .map(|paras| serde_json::from_str(paras))
+            .map(|dir| FileExport::new(printer.clone(), dir, self.save_format))
             .transpose()
-            .map_err(|_| ())
+    }
+}
+
+impl RunArgs {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    /// The parameters given as JSON or read from files, merged in the order
+    /// they were given
+    pub fn parameters(&self) -> Result<Map<String, Value>> {
+        params::load_all(self.sources())
+    }
+
+    /// The JSON, files (`@params.yaml`) and stdin (`-`) given before any flags
+    fn sources(&self) -> &[String] {
+        let count = self
+            .params
+            .iter()
+            .take_while(|param| *param == "-" || !param.starts_with('-'))
+            .count();
+        &self.params[..count]
+    }
+
+    /// Flags given for individual parameters of the plan
+    pub fn flags(&self) -> &[String] {
+        &self.params[self.sources().len()..]
+    }
+
+    /// Whether help was requested, either for `run` or for a plan
+    pub fn help(&self) -> bool {
+        self.help
+            || self
+                .flags()
+                .iter()
+                .any(|flag| flag == "--help" || flag == "-h")
     }
 
     pub fn foreground(&self) -> bool {
@@ -86,11 +374,14 @@ impl RunArgs {
     }
 
     pub(crate) fn instrument_session(&self) -> Value {
-        (self.instrument_session.as_str()).into()
+        self.instrument_session
+            .as_deref()
+            .unwrap_or_default()
+            .into()
     }
 }
 
-#[derive(Debug, Serialize, Parser)]
+#[derive(Debug, Serialize, Args)]
 pub struct PackageFilter {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[clap(short, long)]
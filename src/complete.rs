@@ -0,0 +1,262 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use clap::{CommandFactory, FromArgMatches, ValueEnum};
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::{Bash, EnvCompleter, Fish, Zsh};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
+use tokio::time;
+
+use crate::Client;
+use crate::cli::{Cli, ConnectionArgs};
+use crate::config::{self, ConfigFile, ServerConfig};
+use crate::entities::{DeviceList, PlanList, TaskList};
+use crate::error::{BcliError, Result};
+use crate::output::OutputFormat;
+
+/// Environment variable used by the registration scripts to ask bcli for
+/// completions
+pub const COMPLETE_VAR: &str = "BCLI_COMPLETE";
+/// How long names fetched from the server are reused before fetching them again
+const CACHE_TTL: Duration = Duration::from_secs(60);
+/// How long to wait for the server before giving up (and using stale names if
+/// there are any) so that a slow server doesn't hang the shell
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Shells that completion scripts can be generated for
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Print the script that registers bcli's completions with a shell
+pub fn print_registration(shell: Shell) -> Result<()> {
+    let completer: &dyn EnvCompleter = match shell {
+        Shell::Bash => &Bash,
+        Shell::Zsh => &Zsh,
+        Shell::Fish => &Fish,
+    };
+    // Use the full path so that completions work even if this bcli is not the
+    // one on the PATH
+    let program = std::env::current_exe()
+        .map(|exe| exe.display().to_string())
+        .unwrap_or_else(|_| "bcli".into());
+    let mut script = Vec::new();
+    completer
+        .write_registration(COMPLETE_VAR, "bcli", "bcli", &program, &mut script)
+        .map_err(|e| BcliError::Io("<stdout>".into(), e))?;
+    print!("{}", String::from_utf8_lossy(&script));
+    Ok(())
+}
+
+/// Complete the names of plans
+pub fn plans(current: &OsStr) -> Vec<CompletionCandidate> {
+    Names::Plans.complete(current)
+}
+
+/// Complete the names of devices
+pub fn devices(current: &OsStr) -> Vec<CompletionCandidate> {
+    Names::Devices.complete(current)
+}
+
+/// Complete the IDs of tasks
+pub fn tasks(current: &OsStr) -> Vec<CompletionCandidate> {
+    Names::Tasks.complete(current)
+}
+
+/// The lists of names that are fetched from the server for completion
+#[derive(Debug, Clone, Copy)]
+pub enum Names {
+    Plans,
+    Devices,
+    Tasks,
+}
+
+/// A possible completion along with a description to show next to it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub value: String,
+    pub help: Option<String>,
+}
+
+impl Names {
+    fn complete(self, current: &OsStr) -> Vec<CompletionCandidate> {
+        let current = current.to_string_lossy();
+        let Some(server) = server() else {
+            return vec![];
+        };
+        let cache = cache_file(self, &server);
+        let entries = cache
+            .as_ref()
+            .and_then(|path| read_cache(path, Some(CACHE_TTL)))
+            .or_else(|| {
+                let fetched = self.fetch_blocking(server)?;
+                if let Some(path) = &cache {
+                    write_cache(path, &fetched);
+                }
+                Some(fetched)
+            })
+            .or_else(|| cache.as_ref().and_then(|path| read_cache(path, None)))
+            .unwrap_or_default();
+        entries
+            .into_iter()
+            .filter(|entry| entry.value.starts_with(&*current))
+            .map(|entry| CompletionCandidate::new(entry.value).help(entry.help.map(Into::into)))
+            .collect()
+    }
+
+    /// Fetch the names from outside of an async runtime, giving up if the
+    /// server is too slow
+    fn fetch_blocking(self, server: ServerConfig) -> Option<Vec<Entry>> {
+        let client = Client::new(server, OutputFormat::default());
+        let runtime = Runtime::new().ok()?;
+        runtime
+            .block_on(async { time::timeout(FETCH_TIMEOUT, self.fetch(&client)).await })
+            .ok()?
+            .ok()
+    }
+
+    pub async fn fetch(self, client: &Client) -> Result<Vec<Entry>> {
+        Ok(match self {
+            Names::Plans => client
+                .get::<PlanList>(client.endpoint(&["plans"])?)
+                .await?
+                .plans
+                .into_iter()
+                .map(|plan| Entry {
+                    value: plan.name,
+                    help: plan
+                        .description
+                        .and_then(|desc| desc.lines().next().map(str::to_owned)),
+                })
+                .collect(),
+            Names::Devices => client
+                .get::<DeviceList>(client.endpoint(&["devices"])?)
+                .await?
+                .into_inner()
+                .into_iter()
+                .map(|device| Entry {
+                    value: device.name,
+                    help: None,
+                })
+                .collect(),
+            Names::Tasks => client
+                .get::<TaskList>(client.endpoint(&["tasks"])?)
+                .await?
+                .tasks
+                .into_iter()
+                .map(|task| Entry {
+                    value: task.task_id.to_string(),
+                    help: Some(format!("{} ({})", task.task.name, task.status())),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// The server to complete names from, using any connection options already
+/// on the command line being completed
+fn server() -> Option<ServerConfig> {
+    // Completions are requested as `bcli -- bcli <words being completed>`
+    let words = std::env::args_os().skip_while(|arg| arg != "--").skip(1);
+    let matches = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(words)
+        .ok()?;
+    let args = ConnectionArgs::from_arg_matches(&matches).ok()?;
+    ConfigFile::load(args.config.as_deref())
+        .ok()?
+        .resolve(&args)
+        .ok()
+}
+
+/// Names are cached per server so that switching profiles doesn't offer the
+/// names from another beamline
+fn cache_file(names: Names, server: &ServerConfig) -> Option<PathBuf> {
+    let server = server
+        .url
+        .as_str()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let names = match names {
+        Names::Plans => "plans",
+        Names::Devices => "devices",
+        Names::Tasks => "tasks",
+    };
+    Some(
+        config::cache_dir()?
+            .join("completions")
+            .join(format!("{names}-{server}.json")),
+    )
+}
+
+/// Read cached names, ignoring them if they are older than `max_age`
+fn read_cache(path: &Path, max_age: Option<Duration>) -> Option<Vec<Entry>> {
+    if let Some(max_age) = max_age {
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age > max_age {
+            return None;
+        }
+    }
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+/// Cache names for next time - failing to is not worth interrupting
+/// completion for
+///
+/// The names are written to a temporary file that is renamed over the cache so
+/// that completions running at the same time never read a partial file.
+fn write_cache(path: &Path, entries: &[Entry]) {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let Ok(json) = serde_json::to_vec(entries) else {
+        return;
+    };
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    if fs::write(&tmp, json)
+        .and_then(|_| fs::rename(&tmp, path))
+        .is_err()
+    {
+        let _ = fs::remove_file(&tmp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::{CACHE_TTL, Entry, read_cache, write_cache};
+
+    #[test]
+    fn cache_is_replaced_whole() {
+        let dir = std::env::temp_dir().join(format!("bcli-complete-{}", Uuid::new_v4()));
+        let path = dir.join("completions").join("plans-server.json");
+        let entry = |value: &str| Entry {
+            value: value.into(),
+            help: None,
+        };
+        write_cache(&path, &[entry("count"), entry("scan")]);
+        write_cache(&path, &[entry("count")]);
+        let cached = read_cache(&path, Some(CACHE_TTL)).expect("cache written");
+        let names = cached.iter().map(|e| e.value.as_str()).collect::<Vec<_>>();
+        // Nothing is left behind next to the cache
+        let files = fs::read_dir(path.parent().expect("cache is in a directory"))
+            .expect("cache directory exists")
+            .count();
+        _ = fs::remove_dir_all(&dir);
+        assert_eq!(names, ["count"]);
+        assert_eq!(files, 1);
+    }
+}
@@ -0,0 +1,463 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fs, io};
+
+use clap::ValueEnum;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::cli::ConnectionArgs;
+
+const DEFAULT_URL: &str = "http://localhost:8000";
+const DEFAULT_BROKER_HOST: &str = "localhost";
+const DEFAULT_MQTT_PORT: u16 = 1883;
+const DEFAULT_STOMP_PORT: u16 = 61613;
+
+/// Contents of the bcli configuration file
+///
+/// ```toml
+/// default_profile = "i22"
+///
+/// [profiles.i22]
+/// url = "http://i22-blueapi:8000"
+/// mqtt = "i22-broker:8883"
+/// qos = 1
+/// mqtt_username = "i22-user"
+/// mqtt_ca = "/etc/ssl/i22-ca.pem"
+///
+/// [profiles.p45]
+/// url = "http://p45-blueapi:8000"
+/// event_bus = "stomp"
+/// stomp = "p45-activemq:61613"
+/// stomp_username = "p45-user"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Profile to use if none is given on the command line
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// Connection details for a single blueapi server
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    url: Option<Url>,
+    event_bus: Option<EventBus>,
+    mqtt: Option<MqttAddress>,
+    qos: Option<Qos>,
+    mqtt_username: Option<String>,
+    mqtt_tls: Option<bool>,
+    mqtt_ca: Option<PathBuf>,
+    mqtt_cert: Option<PathBuf>,
+    mqtt_key: Option<PathBuf>,
+    mqtt_keep_alive: Option<u64>,
+    mqtt_last_will: Option<LastWill>,
+    stomp: Option<StompAddress>,
+    stomp_username: Option<String>,
+}
+
+/// The kind of broker that blueapi publishes events to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EventBus {
+    #[default]
+    Mqtt,
+    /// eg ActiveMQ or RabbitMQ
+    Stomp,
+}
+
+/// MQTT quality of service used when subscribing to events
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(try_from = "u8")]
+#[allow(clippy::enum_variant_names, reason = "Named after the MQTT levels")]
+pub enum Qos {
+    /// Events may be lost if the connection is unreliable
+    #[value(name = "0")]
+    AtMostOnce,
+    /// Events are retried until acknowledged and may be duplicated
+    #[default]
+    #[value(name = "1")]
+    AtLeastOnce,
+    /// Events are delivered exactly once at the cost of extra round trips
+    #[value(name = "2")]
+    ExactlyOnce,
+}
+
+impl TryFrom<u8> for Qos {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Qos::AtMostOnce),
+            1 => Ok(Qos::AtLeastOnce),
+            2 => Ok(Qos::ExactlyOnce),
+            _ => Err(format!("Invalid QoS {value}, expected 0, 1 or 2")),
+        }
+    }
+}
+
+/// Message the broker should publish if bcli disconnects without saying
+/// goodbye, eg because it was killed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LastWill {
+    pub topic: String,
+    pub payload: String,
+    #[serde(default)]
+    pub qos: Qos,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+/// Host and port of a broker that blueapi publishes events to, with the port
+/// defaulting to the standard one for the protocol
+///
+/// IPv6 addresses are given in brackets, eg `[::1]:1883`, and stored without
+/// them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct BrokerAddress<const DEFAULT_PORT: u16> {
+    pub host: String,
+    pub port: u16,
+}
+
+pub type MqttAddress = BrokerAddress<DEFAULT_MQTT_PORT>;
+pub type StompAddress = BrokerAddress<DEFAULT_STOMP_PORT>;
+
+impl<const DEFAULT_PORT: u16> FromStr for BrokerAddress<DEFAULT_PORT> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, port) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (host, port) = rest
+                    .split_once(']')
+                    .ok_or_else(|| format!("Missing ']' in broker address: '{s}'"))?;
+                match port {
+                    "" => (host, None),
+                    port => (
+                        host,
+                        Some(port.strip_prefix(':').ok_or_else(|| {
+                            format!("Expected ':' after ']' in broker address: '{s}'")
+                        })?),
+                    ),
+                }
+            }
+            None if s.matches(':').count() > 1 => {
+                return Err(format!(
+                    "IPv6 broker addresses must be in brackets, eg '[::1]:1883': '{s}'"
+                ));
+            }
+            None => match s.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("Invalid port in broker address: '{port}'"))?,
+            None => DEFAULT_PORT,
+        };
+        Ok(Self {
+            host: host.into(),
+            port,
+        })
+    }
+}
+
+impl<const DEFAULT_PORT: u16> BrokerAddress<DEFAULT_PORT> {
+    /// The host as it is written in an address, with IPv6 addresses in
+    /// brackets
+    pub fn url_host(&self) -> Cow<'_, str> {
+        match self.host.contains(':') {
+            true => format!("[{}]", self.host).into(),
+            false => self.host.as_str().into(),
+        }
+    }
+}
+
+impl<const DEFAULT_PORT: u16> TryFrom<String> for BrokerAddress<DEFAULT_PORT> {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl<const DEFAULT_PORT: u16> Display for BrokerAddress<DEFAULT_PORT> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.url_host(), self.port)
+    }
+}
+
+impl<const DEFAULT_PORT: u16> Default for BrokerAddress<DEFAULT_PORT> {
+    fn default() -> Self {
+        Self {
+            host: DEFAULT_BROKER_HOST.into(),
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+/// Fully resolved connection settings used by the client
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub url: Url,
+    pub event_bus: EventBus,
+    pub mqtt: MqttAddress,
+    pub qos: Qos,
+    pub mqtt_client: MqttClientConfig,
+    pub stomp: StompAddress,
+    /// Login for the STOMP broker, if it requires one
+    pub stomp_username: Option<String>,
+}
+
+impl ServerConfig {
+    /// The URL of an endpoint below the server's URL, eg `["tasks", id]`
+    ///
+    /// Any path in the server's URL is kept and each segment is percent
+    /// encoded, so IDs and names can't change which endpoint is used.
+    pub fn endpoint(&self, segments: &[&str]) -> Result<Url, ConfigError> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| {
+                ConfigError::InvalidUrl(
+                    self.url.to_string(),
+                    url::ParseError::RelativeUrlWithCannotBeABaseBase,
+                )
+            })?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+}
+
+/// How bcli identifies itself to the MQTT broker and secures the connection
+#[derive(Debug, Clone, Default)]
+pub struct MqttClientConfig {
+    pub username: Option<String>,
+    pub tls: Option<MqttTls>,
+    /// Seconds between pings when nothing else is sent (0 to disable)
+    pub keep_alive: Option<u64>,
+    pub last_will: Option<LastWill>,
+}
+
+/// Certificates used for a TLS connection to the MQTT broker
+#[derive(Debug, Clone, Default)]
+pub struct MqttTls {
+    /// CA bundle to verify the broker with instead of the system certificates
+    pub ca: Option<PathBuf>,
+    /// Client certificate and key to authenticate with
+    pub client_auth: Option<(PathBuf, PathBuf)>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownProfile(String),
+    InvalidUrl(String, url::ParseError),
+    MqttTls(&'static str),
+    /// A broker username was given but no password could be found for it
+    MissingPassword {
+        account: String,
+        var: &'static str,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => {
+                write!(f, "Couldn't read config file {}: {e}", path.display())
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "Invalid config file {}: {}", path.display(), e.message())
+            }
+            ConfigError::UnknownProfile(name) => write!(f, "No profile named '{name}' in config"),
+            ConfigError::InvalidUrl(url, e) => write!(f, "Invalid URL '{url}': {e}"),
+            ConfigError::MqttTls(msg) => write!(f, "Invalid MQTT TLS settings: {msg}"),
+            ConfigError::MissingPassword { account, var } => write!(
+                f,
+                "No password found for {account}: set {var} or store it in the keyring \
+                 (service 'bcli', account '{account}')"
+            ),
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Load the config from the given path, or the default location if no path is given.
+    ///
+    /// A missing file at the default location is not an error and results in an
+    /// empty config.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if !required && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(ConfigError::Read(path, e)),
+        };
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path, e))
+    }
+
+    /// Combine the selected profile with any overrides given on the command line
+    pub fn resolve(&self, args: &ConnectionArgs) -> Result<ServerConfig, ConfigError> {
+        let profile = match args.profile.as_ref().or(self.default_profile.as_ref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ConfigError::UnknownProfile(name.clone()))?,
+            None => Profile::default(),
+        };
+        let tls = mqtt_tls(args, &profile)?;
+        Ok(ServerConfig {
+            url: args.url.clone().or(profile.url).unwrap_or_else(default_url),
+            event_bus: args.event_bus.or(profile.event_bus).unwrap_or_default(),
+            mqtt: args.mqtt.clone().or(profile.mqtt).unwrap_or_default(),
+            qos: args.qos.or(profile.qos).unwrap_or_default(),
+            mqtt_client: MqttClientConfig {
+                username: args.mqtt_username.clone().or(profile.mqtt_username),
+                tls,
+                keep_alive: args.mqtt_keep_alive.or(profile.mqtt_keep_alive),
+                last_will: profile.mqtt_last_will,
+            },
+            stomp: args.stomp.clone().or(profile.stomp).unwrap_or_default(),
+            stomp_username: args.stomp_username.clone().or(profile.stomp_username),
+        })
+    }
+}
+
+/// TLS is used if it is enabled explicitly or if any certificates are given
+fn mqtt_tls(args: &ConnectionArgs, profile: &Profile) -> Result<Option<MqttTls>, ConfigError> {
+    let ca = args.mqtt_ca.clone().or(profile.mqtt_ca.clone());
+    let cert = args.mqtt_cert.clone().or(profile.mqtt_cert.clone());
+    let key = args.mqtt_key.clone().or(profile.mqtt_key.clone());
+    let client_auth = match (cert, key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => {
+            return Err(ConfigError::MqttTls(
+                "a client certificate and key must be given together",
+            ));
+        }
+    };
+    if client_auth.is_some() && ca.is_none() {
+        return Err(ConfigError::MqttTls(
+            "a CA file must be given to use a client certificate",
+        ));
+    }
+    let enabled = args.mqtt_tls || profile.mqtt_tls.unwrap_or_default();
+    Ok((enabled || ca.is_some() || client_auth.is_some()).then_some(MqttTls { ca, client_auth }))
+}
+
+fn default_url() -> Url {
+    Url::parse(DEFAULT_URL).expect("Default URL is valid")
+}
+
+/// `$XDG_CONFIG_HOME/bcli/config.toml`, falling back to `~/.config/bcli/config.toml`
+fn default_config_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("config.toml"))
+}
+
+/// `$XDG_CACHE_HOME/bcli`, falling back to `~/.cache/bcli`
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$XDG_STATE_HOME/bcli`, falling back to `~/.local/state/bcli`
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// bcli's directory within the XDG base directory named by `var`, or within
+/// `default` (relative to the home directory) if it isn't set
+fn xdg_dir(var: &str, default: &str) -> Option<PathBuf> {
+    let base = env::var_os(var)
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(default)))?;
+    Some(base.join("bcli"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MqttAddress, ServerConfig, StompAddress};
+
+    fn server(url: &str) -> ServerConfig {
+        ServerConfig {
+            url: url.parse().expect("valid url"),
+            event_bus: Default::default(),
+            mqtt: MqttAddress::default(),
+            qos: Default::default(),
+            mqtt_client: Default::default(),
+            stomp: StompAddress::default(),
+            stomp_username: None,
+        }
+    }
+
+    #[test]
+    fn endpoints() {
+        for (base, segments, expected) in [
+            ("http://host:8000", &["plans"][..], "http://host:8000/plans"),
+            (
+                "http://host/blueapi",
+                &["plans"],
+                "http://host/blueapi/plans",
+            ),
+            (
+                "http://host/blueapi/",
+                &["worker", "task"],
+                "http://host/blueapi/worker/task",
+            ),
+            (
+                "http://host",
+                &["plans", "a/b?c"],
+                "http://host/plans/a%2Fb%3Fc",
+            ),
+        ] {
+            let url = server(base).endpoint(segments).expect("valid endpoint");
+            assert_eq!(url.as_str(), expected);
+        }
+        assert!(server("localhost:8000").endpoint(&["plans"]).is_err());
+    }
+
+    #[test]
+    fn broker_addresses() {
+        for (text, host, port) in [
+            ("broker", "broker", 1883),
+            ("broker:1884", "broker", 1884),
+            ("10.0.0.1:1884", "10.0.0.1", 1884),
+            ("[::1]", "::1", 1883),
+            ("[::1]:1884", "::1", 1884),
+        ] {
+            let address = text.parse::<MqttAddress>().expect("valid address");
+            assert_eq!(
+                (address.host.as_str(), address.port),
+                (host, port),
+                "{text}"
+            );
+        }
+        for text in ["::1", "[::1", "[::1]1884", "broker:port", "broker:"] {
+            assert!(text.parse::<MqttAddress>().is_err(), "{text}");
+        }
+        let address = "[::1]:1884".parse::<MqttAddress>().expect("valid address");
+        assert_eq!(address.to_string(), "[::1]:1884");
+    }
+}
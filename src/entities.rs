@@ -1,11 +1,15 @@
 use std::fmt::{Debug, Display};
+use std::str::FromStr;
 
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use uuid::Uuid;
 
+use crate::output::{Row, Table, Tabular};
+
 /// One of the bluesky protocols than can be implemented by devices in blueapi
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Protocol {
     name: String,
     types: Vec<String>,
@@ -22,9 +26,9 @@ impl Display for Protocol {
 }
 
 /// Device available in blueapi along with the protocols it implements
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Device {
-    name: String,
+    pub name: String,
     protocols: Vec<Protocol>,
 }
 
@@ -34,7 +38,7 @@ impl Display for Device {
         let mut proto_iter = self.protocols.iter();
         if let Some(first) = proto_iter.next() {
             write!(f, "\n\t{first}")?;
-            while let Some(next) = proto_iter.next() {
+            for next in proto_iter {
                 write!(f, ", {next}")?;
             }
         }
@@ -42,6 +46,20 @@ impl Display for Device {
     }
 }
 
+impl Row for Device {
+    const HEADERS: &'static [&'static str] = &["NAME", "PROTOCOLS"];
+    fn cells(&self) -> Vec<String> {
+        let protocols = self.protocols.iter().map(|p| p.to_string());
+        vec![self.name.clone(), protocols.collect::<Vec<_>>().join(", ")]
+    }
+}
+
+impl Tabular for Device {
+    fn table(&self) -> Table {
+        Table::new(Self::HEADERS).row(self.cells())
+    }
+}
+
 /// List of devices as returned by the blueapi server
 #[derive(Debug, Deserialize)]
 pub struct DeviceList {
@@ -55,10 +73,31 @@ impl DeviceList {
 }
 
 /// Details of a plan available in blueapi
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PlanSpec {
     pub name: String,
     pub description: Option<String>,
+    /// JSON schema of the plan's parameters
+    #[serde(default)]
+    pub schema: Value,
+}
+
+impl Row for PlanSpec {
+    const HEADERS: &'static [&'static str] = &["NAME", "DESCRIPTION"];
+    fn cells(&self) -> Vec<String> {
+        // Only the summary line of the docstring fits in a table
+        let summary = self
+            .description
+            .as_deref()
+            .and_then(|d| d.trim().lines().next());
+        vec![self.name.clone(), summary.unwrap_or_default().into()]
+    }
+}
+
+impl Tabular for PlanSpec {
+    fn table(&self) -> Table {
+        Table::new(Self::HEADERS).row(self.cells())
+    }
 }
 
 /// List of plans as returned by the blueapi server
@@ -70,11 +109,90 @@ pub struct PlanList {
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
 pub struct TaskId(pub Uuid);
 
+impl Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for TaskId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(TaskId)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TaskReference {
     pub task_id: TaskId,
 }
 
+/// The task currently being run by the worker, if any
+#[derive(Debug, Deserialize)]
+pub struct ActiveTask {
+    pub task_id: Option<TaskId>,
+}
+
+/// A task known to the worker along with its current status
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrackableTask {
+    pub task_id: TaskId,
+    pub task: Task,
+    pub is_complete: bool,
+    pub is_pending: bool,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+impl TrackableTask {
+    pub fn status(&self) -> &'static str {
+        match (self.is_pending, self.is_complete, self.errors.is_empty()) {
+            (true, _, _) => "pending",
+            (false, false, _) => "running",
+            (false, true, true) => "complete",
+            (false, true, false) => "failed",
+        }
+    }
+}
+
+impl Row for TrackableTask {
+    const HEADERS: &'static [&'static str] = &["TASK", "PLAN", "STATUS", "ERRORS"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.task_id.to_string(),
+            self.task.name.clone(),
+            self.status().into(),
+            self.errors.len().to_string(),
+        ]
+    }
+}
+
+impl Tabular for TrackableTask {
+    fn table(&self) -> Table {
+        let params = self.task.params.to_string();
+        Table::new(&["FIELD", "VALUE"])
+            .row(["Task", &self.task_id.to_string()])
+            .row(["Plan", &self.task.name])
+            .row(["Status", self.status()])
+            .row(["Parameters", &params])
+            .rows(self.errors.iter().map(|e| ["Error", e]))
+    }
+}
+
+/// A plan along with the parameters it should be run with
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Task {
+    pub name: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TaskList {
+    pub tasks: Vec<TrackableTask>,
+}
+
 impl Debug for Protocol {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name)?;
@@ -96,7 +214,7 @@ pub struct NewState {
     pub defer: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum WorkerState {
     Idle,
@@ -111,20 +229,50 @@ pub enum WorkerState {
     Unknown,
 }
 
-#[derive(Debug, Deserialize)]
+impl Display for WorkerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&format!("{self:?}").to_uppercase())
+    }
+}
+
+impl Tabular for WorkerState {
+    fn table(&self) -> Table {
+        Table::new(&["STATE"]).row([self])
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct EnvironmentState {
     pub environment_id: Uuid,
     pub initialized: bool,
     pub error_message: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Tabular for EnvironmentState {
+    fn table(&self) -> Table {
+        Table::new(&["ENVIRONMENT", "INITIALIZED", "ERROR"]).row([
+            self.environment_id.to_string(),
+            self.initialized.to_string(),
+            self.error_message.clone().unwrap_or_default(),
+        ])
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PythonEnvironment {
     pub installed_packages: Vec<PackageInfo>,
     pub scratch_enabled: bool,
 }
 
-#[derive(Debug, Deserialize)]
+impl Tabular for PythonEnvironment {
+    fn table(&self) -> Table {
+        self.installed_packages
+            .table()
+            .note(format!("Scratch enabled: {}", self.scratch_enabled))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PackageInfo {
     name: String,
     version: String,
@@ -136,6 +284,13 @@ impl Display for PackageInfo {
     }
 }
 
+impl Row for PackageInfo {
+    const HEADERS: &'static [&'static str] = &["PACKAGE", "VERSION"];
+    fn cells(&self) -> Vec<String> {
+        vec![self.name.clone(), self.version.clone()]
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum SourceInfo {
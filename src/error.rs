@@ -0,0 +1,158 @@
+use std::fmt::Display;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use reqwest::{StatusCode, Url};
+use serde_json::Value;
+
+use crate::config::ConfigError;
+use crate::entities::TaskId;
+
+/// Errors that can stop bcli from completing a command
+///
+/// Each variant maps to a distinct process exit code (see [`BcliError::exit_code`])
+/// so that scripts can tell the difference between, eg, an unreachable server and
+/// a plan that does not exist.
+#[derive(Debug)]
+pub enum BcliError {
+    /// The config file could not be read or did not contain the requested profile
+    Config(ConfigError),
+    /// The request could not be sent or no response was received
+    Transport(Url, reqwest::Error),
+    /// The server responded with an unsuccessful status code
+    Status {
+        url: Url,
+        status: StatusCode,
+        detail: Option<String>,
+    },
+    /// The server responded with something that was not what we expected
+    Deserialize(Url, serde_json::Error),
+    /// The event bus could not be reached or subscribed to
+    EventBus(String),
+    /// Something did not happen in the time we were prepared to wait for it
+    Timeout(String),
+    /// The parameters given for a plan could not be used
+    InvalidParameters(String),
+    /// The blueapi environment failed to load
+    Environment(String),
+    /// A local file could not be read or written
+    Io(PathBuf, io::Error),
+    /// A task ran but failed, was aborted or reported errors
+    TaskFailed(TaskId),
+    /// A command needed the worker to be running a task but it wasn't
+    NoActiveTask,
+    /// The worker panicked while we were waiting for it
+    WorkerPanicked,
+}
+
+pub type Result<T, E = BcliError> = std::result::Result<T, E>;
+
+impl BcliError {
+    /// The process exit code used to report this error
+    ///
+    /// | Code | Meaning                                              |
+    /// |------|------------------------------------------------------|
+    /// | 1    | Unexpected error                                     |
+    /// | 2    | Invalid command line arguments (reported by clap)    |
+    /// | 3    | Invalid configuration                                |
+    /// | 4    | Couldn't connect to the blueapi server               |
+    /// | 5    | Server rejected the request (4xx/5xx status)         |
+    /// | 6    | Unexpected response from the server                  |
+    /// | 7    | Couldn't connect or subscribe to the event bus       |
+    /// | 8    | Timed out                                            |
+    /// | 9    | Invalid plan parameters                              |
+    /// | 10   | Environment failed to load                           |
+    /// | 11   | Couldn't read or write a local file                  |
+    /// | 12   | Plan failed, was aborted or reported errors          |
+    /// | 13   | No task is running                                   |
+    /// | 14   | Worker panicked                                      |
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            BcliError::Config(_) => 3,
+            BcliError::Transport(..) => 4,
+            BcliError::Status { .. } => 5,
+            BcliError::Deserialize(..) => 6,
+            BcliError::EventBus(_) => 7,
+            BcliError::Timeout(_) => 8,
+            BcliError::InvalidParameters(_) => 9,
+            BcliError::Environment(_) => 10,
+            BcliError::Io(..) => 11,
+            BcliError::TaskFailed(_) => 12,
+            BcliError::NoActiveTask => 13,
+            BcliError::WorkerPanicked => 14,
+        })
+    }
+
+    /// Build a status error from the body of an unsuccessful response
+    ///
+    /// blueapi (via FastAPI) reports errors as `{"detail": ...}` so use that if
+    /// it's there, otherwise fall back to the raw body.
+    pub fn status(url: Url, status: StatusCode, body: &str) -> Self {
+        let detail = match serde_json::from_str::<Value>(body) {
+            Ok(Value::Object(mut obj)) => match obj.remove("detail") {
+                Some(Value::String(detail)) => Some(detail),
+                Some(detail) => Some(detail.to_string()),
+                None => Some(Value::Object(obj).to_string()),
+            },
+            _ => Some(body.trim().to_owned()).filter(|b| !b.is_empty()),
+        };
+        BcliError::Status {
+            url,
+            status,
+            detail,
+        }
+    }
+}
+
+impl Display for BcliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BcliError::Config(e) => write!(f, "{e}"),
+            BcliError::Transport(url, e) => {
+                write!(f, "Couldn't connect to blueapi at {url}: ")?;
+                // reqwest's Display only gives the outermost error which is not
+                // usually the useful one
+                match std::error::Error::source(e) {
+                    Some(src) => write!(f, "{}", root_cause(src)),
+                    None => write!(f, "{e}"),
+                }
+            }
+            BcliError::Status {
+                url,
+                status,
+                detail,
+            } => {
+                write!(f, "Request to {url} failed ({status})")?;
+                if let Some(detail) = detail {
+                    write!(f, ": {detail}")?;
+                }
+                Ok(())
+            }
+            BcliError::Deserialize(url, e) => write!(f, "Unexpected response from {url}: {e}"),
+            BcliError::EventBus(msg) => write!(f, "Event bus error: {msg}"),
+            BcliError::Timeout(msg) => write!(f, "Timed out: {msg}"),
+            BcliError::InvalidParameters(msg) => write!(f, "Invalid plan parameters: {msg}"),
+            BcliError::Environment(msg) => write!(f, "Environment failed to load: {msg}"),
+            BcliError::Io(path, e) => write!(f, "Couldn't access {}: {e}", path.display()),
+            BcliError::TaskFailed(id) => write!(f, "Task {id} did not complete successfully"),
+            BcliError::NoActiveTask => write!(f, "The worker is not running a task"),
+            BcliError::WorkerPanicked => write!(f, "The worker has panicked"),
+        }
+    }
+}
+
+impl std::error::Error for BcliError {}
+
+impl From<ConfigError> for BcliError {
+    fn from(value: ConfigError) -> Self {
+        BcliError::Config(value)
+    }
+}
+
+fn root_cause(mut err: &dyn std::error::Error) -> &dyn std::error::Error {
+    while let Some(src) = err.source() {
+        err = src;
+    }
+    err
+}
@@ -0,0 +1,420 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::time;
+
+use crate::config::{EventBus, ServerConfig};
+use crate::error::{BcliError, Result};
+use crate::messages::Message;
+use crate::progress::Printer;
+use mqtt::MqttSubscription;
+use stomp::StompSubscription;
+
+mod mqtt;
+mod stomp;
+
+/// Slowest speed a recording can be replayed at, so the gaps between messages
+/// stay within what can be waited for
+const MIN_SPEED: f64 = 0.001;
+/// How many messages a slow consumer of [`SharedEvents`] can fall behind by
+/// before messages are dropped
+const SHARED_CAPACITY: usize = 1024;
+/// How long to wait for a broker to accept the connection and subscription
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay before the first attempt to reconnect after losing the connection
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest delay between attempts to reconnect
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A subscription to the events blueapi publishes to a broker
+trait Subscription: Sized {
+    /// Connect to the broker and subscribe to worker events
+    ///
+    /// This only returns once the broker has acknowledged the subscription.
+    fn connect(server: &ServerConfig) -> impl Future<Output = Result<Self>> + Send;
+
+    /// Wait for the next payload, returning it along with the topic (or
+    /// destination) it was published to
+    fn next(&mut self) -> impl Future<Output = Result<(String, Vec<u8>)>> + Send;
+}
+
+/// Something received from an event stream
+#[derive(Debug, Clone)]
+// Almost everything received is a message so boxing them would only add an
+// allocation for each one
+#[allow(clippy::large_enum_variant)]
+pub enum Received {
+    Message(Message),
+    /// A change in the state of the connection (or a problem restoring it)
+    /// that should be reported to the user
+    Notice(String),
+}
+
+/// Messages received from the event bus, along with whether the connection to
+/// the broker is currently up
+pub struct EventStream {
+    messages: Receiver<Received>,
+    connected: watch::Receiver<bool>,
+    printer: Printer,
+}
+
+impl EventStream {
+    /// The next message, printing any notices received before it
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            match self.next().await? {
+                Received::Message(msg) => return Some(msg),
+                Received::Notice(notice) => self.printer.eprintln(notice),
+            }
+        }
+    }
+
+    /// The next message or notice
+    pub async fn next(&mut self) -> Option<Received> {
+        self.messages.recv().await
+    }
+
+    /// Print notices with the given printer so they don't disturb its
+    /// progress bars
+    pub fn with_printer(mut self, printer: Printer) -> Self {
+        self.printer = printer;
+        self
+    }
+
+    /// Whether events could have been missed since this was last checked,
+    /// either because the connection is down or because it was lost and
+    /// restored in the meantime
+    pub fn interrupted(&mut self) -> bool {
+        let changed = self.connected.has_changed().unwrap_or(true);
+        !*self.connected.borrow_and_update() || changed
+    }
+}
+
+/// Subscribe to the events published by blueapi
+///
+/// This only returns once the broker has acknowledged the subscription so any
+/// events published after it returns will be received. If the connection is
+/// lost later, it is retried (with increasing delays) until it is restored.
+///
+/// If a recorder is given, every payload received is written to it before
+/// being parsed.
+pub async fn subscribe(server: &ServerConfig, recorder: Option<Recorder>) -> Result<EventStream> {
+    Ok(match server.event_bus {
+        EventBus::Mqtt => receive(
+            server.clone(),
+            MqttSubscription::connect(server).await?,
+            recorder,
+        ),
+        EventBus::Stomp => receive(
+            server.clone(),
+            StompSubscription::connect(server).await?,
+            recorder,
+        ),
+    })
+}
+
+/// Pass on every message from a subscription until nothing is listening any
+/// more, reconnecting whenever the connection is lost
+fn receive<S: Subscription + Send + 'static>(
+    server: ServerConfig,
+    mut subscription: S,
+    mut recorder: Option<Recorder>,
+) -> EventStream {
+    let (tx, rx) = mpsc::channel(10);
+    let (connected_tx, connected) = watch::channel(true);
+    tokio::spawn(async move {
+        loop {
+            match subscription.next().await {
+                Ok((topic, payload)) => {
+                    if let Some(rec) = &mut recorder {
+                        rec.record(&topic, &payload);
+                    }
+                    let received = match parse(&payload) {
+                        Ok(msg) => Received::Message(msg),
+                        Err(notice) => Received::Notice(notice),
+                    };
+                    if tx.send(received).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    connected_tx.send_replace(false);
+                    let notice = format!("Warning: event stream disconnected, reconnecting ({e})");
+                    if !notify(&tx, notice).await {
+                        break;
+                    }
+                    match reconnect(&server, &tx).await {
+                        Some(restored) => subscription = restored,
+                        None => break,
+                    }
+                    connected_tx.send_replace(true);
+                    let notice = "Event stream reconnected, any events sent while it was disconnected were missed";
+                    if !notify(&tx, notice.into()).await {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    EventStream {
+        messages: rx,
+        connected,
+        printer: Printer::default(),
+    }
+}
+
+/// Keep trying to subscribe, doubling the delay after each failure, until it
+/// works or nothing is listening any more
+///
+/// Failures are reported while the delay is still growing and whenever the
+/// error changes, so a long outage doesn't print a line every attempt.
+async fn reconnect<S: Subscription>(server: &ServerConfig, tx: &Sender<Received>) -> Option<S> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut last_error = None;
+    loop {
+        tokio::select! {
+            _ = time::sleep(backoff) => {}
+            _ = tx.closed() => return None,
+        }
+        match S::connect(server).await {
+            Ok(subscription) => return Some(subscription),
+            Err(e) => {
+                let error = e.to_string();
+                let doubled = backoff < MAX_BACKOFF;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                if doubled || last_error.as_ref() != Some(&error) {
+                    let notice = format!(
+                        "Couldn't reconnect to the event bus, retrying in {}s ({error})",
+                        backoff.as_secs_f64()
+                    );
+                    if !notify(tx, notice).await {
+                        return None;
+                    }
+                }
+                last_error = Some(error);
+            }
+        }
+    }
+}
+
+/// Pass on a notice, returning false if nothing is listening any more
+async fn notify(tx: &Sender<Received>, notice: String) -> bool {
+    tx.send(Received::Notice(notice)).await.is_ok()
+}
+
+/// A single subscription to the event bus shared by several consumers, eg all
+/// the commands run from one shell session
+#[derive(Clone)]
+pub struct SharedEvents {
+    messages: broadcast::Sender<Received>,
+    connected: watch::Receiver<bool>,
+}
+
+impl SharedEvents {
+    pub async fn connect(server: &ServerConfig) -> Result<Self> {
+        let mut stream = subscribe(server, None).await?;
+        let (tx, _) = broadcast::channel(SHARED_CAPACITY);
+        let shared = Self {
+            messages: tx.clone(),
+            connected: stream.connected.clone(),
+        };
+        tokio::spawn(async move {
+            // Notices are passed on too so each consumer can print them
+            while let Some(msg) = stream.next().await {
+                // It's fine for nothing to be listening
+                let _ = tx.send(msg);
+            }
+        });
+        Ok(shared)
+    }
+
+    /// The messages received from now on
+    pub fn stream(&self) -> EventStream {
+        let mut shared = self.messages.subscribe();
+        let mut connected = self.connected.clone();
+        connected.mark_unchanged();
+        let (tx, rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            loop {
+                match shared.recv().await {
+                    Ok(msg) => {
+                        if tx.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        let notice = format!(
+                            "Warning: {missed} events were dropped as they were not handled quickly enough"
+                        );
+                        if !notify(&tx, notice).await {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        EventStream {
+            messages: rx,
+            connected,
+            printer: Printer::default(),
+        }
+    }
+}
+
+/// Parse a payload, or describe why it isn't a message
+fn parse(payload: &[u8]) -> Result<Message, String> {
+    serde_json::from_slice(payload).map_err(|e| {
+        format!(
+            "Couldn't parse message: {e}\n{}",
+            String::from_utf8_lossy(payload)
+        )
+    })
+}
+
+/// A single payload received from the event bus, as stored in a recording
+#[derive(Debug, Serialize, Deserialize)]
+struct Recorded {
+    /// Seconds since the unix epoch at which the payload was received
+    received: f64,
+    topic: String,
+    payload: String,
+}
+
+/// Writes every payload received to a file, one JSON object per line
+pub struct Recorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    failed: bool,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(|e| BcliError::Io(path.to_owned(), e))?;
+        Ok(Self {
+            path: path.to_owned(),
+            writer: BufWriter::new(file),
+            failed: false,
+        })
+    }
+
+    fn record(&mut self, topic: &str, payload: &[u8]) {
+        if self.failed {
+            return;
+        }
+        let line = Recorded {
+            received: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            topic: topic.into(),
+            payload: String::from_utf8_lossy(payload).into(),
+        };
+        let written = serde_json::to_writer(&mut self.writer, &line)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(self.writer))
+            // Flush each line so the recording is usable if bcli is killed
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = written {
+            eprintln!("Couldn't write to {}: {e}", self.path.display());
+            self.failed = true;
+        }
+    }
+}
+
+/// Multiplier applied to the timing of a replayed recording, eg `10x`
+#[derive(Debug, Clone, Copy)]
+pub struct Speed(f64);
+
+impl FromStr for Speed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+            Ok(speed) if speed == 0.0 || speed >= MIN_SPEED => Ok(Self(speed)),
+            _ => Err(format!(
+                "Invalid speed '{s}', expected a multiplier of at least {MIN_SPEED} like '10x' (or 0 for no delay)"
+            )),
+        }
+    }
+}
+
+/// Replay a recording made by [`Recorder`], preserving the gaps between
+/// messages (scaled by `speed`)
+///
+/// Lines that can't be read are passed on as notices.
+pub fn replay(path: &Path, speed: Speed) -> Result<EventStream> {
+    let file = File::open(path).map_err(|e| BcliError::Io(path.to_owned(), e))?;
+    let path = path.to_owned();
+    let (tx, rx) = mpsc::channel(10);
+    // A recording can't be disconnected
+    let (connected_tx, connected) = watch::channel(true);
+    tokio::spawn(async move {
+        let _connected = connected_tx;
+        let mut previous: Option<f64> = None;
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let location = format!("{}:{}", path.display(), line_no + 1);
+            let recorded = match line
+                .map_err(|e| e.to_string())
+                .and_then(|line| serde_json::from_str::<Recorded>(&line).map_err(|e| e.to_string()))
+            {
+                Ok(rec) => rec,
+                Err(e) => {
+                    if !notify(&tx, format!("{location}: {e}")).await {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            if let Some(prev) = previous
+                && speed.0 > 0.0
+            {
+                let gap = (recorded.received - prev).max(0.0) / speed.0;
+                match Duration::try_from_secs_f64(gap) {
+                    Ok(gap) => time::sleep(gap).await,
+                    Err(e) => {
+                        let notice = format!("{location}: not waiting before this message: {e}");
+                        if !notify(&tx, notice).await {
+                            break;
+                        }
+                    }
+                }
+            }
+            previous = Some(recorded.received);
+            let received = match parse(recorded.payload.as_bytes()) {
+                Ok(msg) => Received::Message(msg),
+                Err(e) => Received::Notice(format!("{location}: {e}")),
+            };
+            if tx.send(received).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(EventStream {
+        messages: rx,
+        connected,
+        printer: Printer::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Speed;
+
+    #[test]
+    fn speed() {
+        for (text, speed) in [("10x", 10.0), ("0.5", 0.5), ("0", 0.0), ("0.001x", 0.001)] {
+            assert_eq!(text.parse::<Speed>().expect("valid speed").0, speed);
+        }
+        for text in ["-1x", "1e-300x", "nan", "fast"] {
+            assert!(text.parse::<Speed>().is_err(), "{text}");
+        }
+    }
+}
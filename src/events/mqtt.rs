@@ -0,0 +1,271 @@
+use std::path::Path;
+use std::time::Duration;
+
+use rumqttc::{
+    AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, SubscribeReasonCode,
+    TlsConfiguration, Transport,
+};
+use tokio::time;
+use uuid::Uuid;
+
+use super::{CONNECT_TIMEOUT, Subscription};
+use crate::config::{MqttAddress, MqttTls, Qos, ServerConfig};
+use crate::error::{BcliError, Result};
+use crate::keyring;
+
+const TOPIC: &str = "public/worker/event";
+/// Environment variable the password for the MQTT broker is read from
+const PASSWORD_VAR: &str = "BLUEAPI_MQTT_PASSWORD";
+
+/// Worker events received from an MQTT broker
+pub struct MqttSubscription {
+    /// The client has to be kept alive for the connection to stay open
+    _client: AsyncClient,
+    conn: EventLoop,
+    broker: MqttAddress,
+}
+
+impl Subscription for MqttSubscription {
+    async fn connect(server: &ServerConfig) -> Result<Self> {
+        let broker = &server.mqtt;
+        let (client, mut conn) = AsyncClient::new(options(server).await?, 10);
+        client
+            .subscribe(TOPIC, server.qos.into())
+            .await
+            .map_err(|e| {
+                BcliError::EventBus(format!("Couldn't subscribe to worker events: {e}"))
+            })?;
+        time::timeout(CONNECT_TIMEOUT, acknowledged(&mut conn, broker))
+            .await
+            .unwrap_or_else(|_| {
+                Err(BcliError::EventBus(format!(
+                    "Couldn't connect to broker at {broker}: no response within {}s",
+                    CONNECT_TIMEOUT.as_secs()
+                )))
+            })?;
+        Ok(Self {
+            _client: client,
+            conn,
+            broker: broker.clone(),
+        })
+    }
+
+    async fn next(&mut self) -> Result<(String, Vec<u8>)> {
+        loop {
+            match self.conn.poll().await {
+                Ok(Event::Incoming(Packet::Publish(data))) => {
+                    return Ok((data.topic, data.payload.to_vec()));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(BcliError::EventBus(format!(
+                        "Lost connection to broker at {}: {e}",
+                        self.broker
+                    )));
+                }
+            }
+        }
+    }
+}
+
+/// Wait for the broker to acknowledge the subscription
+async fn acknowledged(conn: &mut EventLoop, broker: &MqttAddress) -> Result<()> {
+    loop {
+        match conn.poll().await {
+            Ok(Event::Incoming(Packet::SubAck(ack))) => {
+                return match ack.return_codes.first() {
+                    Some(SubscribeReasonCode::Success(_)) => Ok(()),
+                    _ => Err(BcliError::EventBus(format!(
+                        "Broker at {broker} refused subscription to {TOPIC}"
+                    ))),
+                };
+            }
+            Ok(_) => {}
+            Err(e) => {
+                return Err(BcliError::EventBus(format!(
+                    "Couldn't connect to broker at {broker}: {e}"
+                )));
+            }
+        }
+    }
+}
+
+/// The options used for every connection to the MQTT broker
+async fn options(server: &ServerConfig) -> Result<MqttOptions> {
+    let broker = &server.mqtt;
+    let client = &server.mqtt_client;
+    let mut options = MqttOptions::new(
+        format!("bcli-{}", Uuid::new_v4()),
+        broker.url_host(),
+        broker.port,
+    );
+    if let Some(username) = &client.username {
+        // An empty password (set explicitly with an empty variable) is sent
+        // as it is
+        let password = keyring::broker_password(PASSWORD_VAR, username, &broker.host).await?;
+        options.set_credentials(username, password);
+    }
+    if let Some(tls) = &client.tls {
+        options.set_transport(transport(tls)?);
+    }
+    if let Some(keep_alive) = client.keep_alive {
+        options.set_keep_alive(Duration::from_secs(keep_alive));
+    }
+    if let Some(will) = &client.last_will {
+        options.set_last_will(LastWill::new(
+            &will.topic,
+            will.payload.as_bytes(),
+            will.qos.into(),
+            will.retain,
+        ));
+    }
+    Ok(options)
+}
+
+fn transport(tls: &MqttTls) -> Result<Transport> {
+    let read = |path: &Path| std::fs::read(path).map_err(|e| BcliError::Io(path.to_owned(), e));
+    let config = match &tls.ca {
+        Some(ca) => TlsConfiguration::Simple {
+            ca: read(ca)?,
+            alpn: None,
+            client_auth: match &tls.client_auth {
+                Some((cert, key)) => Some((read(cert)?, read(key)?)),
+                None => None,
+            },
+        },
+        // Verify the broker using the system's certificates
+        None => TlsConfiguration::default(),
+    };
+    Ok(Transport::tls_with_config(config))
+}
+
+impl From<Qos> for QoS {
+    fn from(value: Qos) -> Self {
+        match value {
+            Qos::AtMostOnce => QoS::AtMostOnce,
+            Qos::AtLeastOnce => QoS::AtLeastOnce,
+            Qos::ExactlyOnce => QoS::ExactlyOnce,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{MqttSubscription, TOPIC};
+    use crate::config::{EventBus, MqttAddress, Qos, ServerConfig, StompAddress};
+    use crate::error::Result;
+    use crate::events::Subscription;
+
+    const CONNACK: &[u8] = &[0x20, 0x02, 0x00, 0x00];
+
+    /// Read a packet, returning its type and everything after the fixed header
+    async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let header = stream.read_u8().await.expect("reading packet type");
+        let (mut len, mut shift) = (0usize, 0);
+        loop {
+            let byte = stream.read_u8().await.expect("reading packet length");
+            len |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.expect("reading packet");
+        (header >> 4, body)
+    }
+
+    /// Accept a client and acknowledge its connection, returning the ID of its
+    /// subscription request
+    async fn accept(listener: &TcpListener) -> (TcpStream, [u8; 2]) {
+        let (mut stream, _) = listener.accept().await.expect("client connects");
+        assert_eq!(read_packet(&mut stream).await.0, 1, "CONNECT");
+        stream.write_all(CONNACK).await.expect("writing CONNACK");
+        let (kind, body) = read_packet(&mut stream).await;
+        assert_eq!(kind, 8, "SUBSCRIBE");
+        (stream, [body[0], body[1]])
+    }
+
+    /// Connect to a broker run by `broker` on a local port
+    async fn connect<F>(broker: impl FnOnce(TcpListener) -> F) -> Result<MqttSubscription>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("binding local port");
+        let port = listener.local_addr().expect("bound address").port();
+        tokio::spawn(broker(listener));
+        MqttSubscription::connect(&ServerConfig {
+            url: "http://localhost:8000".parse().expect("valid url"),
+            event_bus: EventBus::Mqtt,
+            mqtt: MqttAddress {
+                host: "127.0.0.1".into(),
+                port,
+            },
+            qos: Qos::AtLeastOnce,
+            mqtt_client: Default::default(),
+            stomp: StompAddress::default(),
+            stomp_username: None,
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn receives_publishes() {
+        let mut sub = connect(|listener| async move {
+            let (mut stream, [hi, lo]) = accept(&listener).await;
+            let mut publish = vec![0x30, (2 + TOPIC.len() + 2) as u8, 0, TOPIC.len() as u8];
+            publish.extend(TOPIC.as_bytes());
+            publish.extend(b"{}");
+            stream
+                .write_all(&[0x90, 0x03, hi, lo, 0x01])
+                .await
+                .expect("writing SUBACK");
+            stream.write_all(&publish).await.expect("writing PUBLISH");
+            // Keep the connection open until the client is done
+            let _ = read_packet(&mut stream).await;
+        })
+        .await
+        .expect("subscription is acknowledged");
+        let (topic, payload) = sub.next().await.expect("message is received");
+        assert_eq!(topic, TOPIC);
+        assert_eq!(payload, b"{}");
+    }
+
+    #[tokio::test]
+    async fn refused_subscription() {
+        let err = connect(|listener| async move {
+            let (mut stream, [hi, lo]) = accept(&listener).await;
+            stream
+                .write_all(&[0x90, 0x03, hi, lo, 0x80])
+                .await
+                .expect("writing SUBACK");
+            let _ = read_packet(&mut stream).await;
+        })
+        .await
+        .err()
+        .expect("subscription is refused");
+        assert!(
+            err.to_string()
+                .ends_with(&format!("refused subscription to {TOPIC}")),
+            "{err}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unacknowledged_subscription() {
+        let err = connect(|listener| async move {
+            let (mut stream, _) = accept(&listener).await;
+            // Never acknowledge the subscription
+            let _ = read_packet(&mut stream).await;
+        })
+        .await
+        .err()
+        .expect("gives up waiting");
+        assert!(err.to_string().ends_with("no response within 5s"), "{err}");
+    }
+}
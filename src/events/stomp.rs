@@ -0,0 +1,603 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use super::{CONNECT_TIMEOUT, Subscription};
+use crate::config::{ServerConfig, StompAddress};
+use crate::error::{BcliError, Result};
+use crate::keyring;
+
+/// Where blueapi publishes worker events when using a STOMP broker
+const DESTINATION: &str = "/topic/public.worker.event";
+/// Largest frame body accepted, so a bad content-length header can't make us
+/// allocate an unreasonable amount of memory
+const MAX_BODY: usize = 64 * 1024 * 1024;
+/// Receipt requested for the subscription so we know when it is active
+const SUBSCRIBE_RECEIPT: &str = "bcli-subscribe";
+/// How often the broker is asked to send heart-beats so that a connection
+/// that has silently gone away is noticed
+const HEART_BEAT: Duration = Duration::from_secs(10);
+/// Environment variable the passcode for the STOMP broker is read from
+const PASSWORD_VAR: &str = "BLUEAPI_STOMP_PASSWORD";
+
+/// Worker events received from a STOMP (1.2) broker, eg ActiveMQ or RabbitMQ
+pub struct StompSubscription<S = TcpStream> {
+    stream: BufReader<S>,
+    broker: StompAddress,
+    /// Messages that arrived before the subscription was confirmed
+    pending: VecDeque<Frame>,
+    /// How long to wait for the next heart-beat or frame before giving up on
+    /// the connection, if the broker agreed to send heart-beats
+    read_timeout: Option<Duration>,
+}
+
+/// A single STOMP frame
+#[derive(Debug)]
+struct Frame {
+    command: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Subscription for StompSubscription {
+    async fn connect(server: &ServerConfig) -> Result<Self> {
+        let broker = &server.stomp;
+        let login = match &server.stomp_username {
+            Some(username) => Some((
+                username.as_str(),
+                keyring::broker_password(PASSWORD_VAR, username, &broker.host).await?,
+            )),
+            None => None,
+        };
+        let connect = async {
+            let stream = TcpStream::connect((broker.host.as_str(), broker.port))
+                .await
+                .map_err(|e| connect_failed(broker, &e))?;
+            let login = login.as_ref().map(|(user, pass)| (*user, pass.as_str()));
+            Self::handshake(stream, broker, login).await
+        };
+        time::timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .unwrap_or_else(|_| {
+                Err(connect_failed(
+                    broker,
+                    &format!("no response within {}s", CONNECT_TIMEOUT.as_secs()),
+                ))
+            })
+    }
+
+    async fn next(&mut self) -> Result<(String, Vec<u8>)> {
+        self.receive().await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> StompSubscription<S> {
+    /// Connect and subscribe over an open stream, waiting for the broker to
+    /// confirm each step
+    async fn handshake(
+        stream: S,
+        broker: &StompAddress,
+        login: Option<(&str, &str)>,
+    ) -> Result<Self> {
+        let failed = |e: &dyn std::fmt::Display| connect_failed(broker, e);
+        let mut sub = Self {
+            stream: BufReader::new(stream),
+            broker: broker.clone(),
+            pending: VecDeque::new(),
+            read_timeout: None,
+        };
+        // The host header is the virtual host - ActiveMQ ignores it and "/" is
+        // RabbitMQ's default
+        let heart_beat = format!("0,{}", HEART_BEAT.as_millis());
+        let mut headers = vec![
+            ("accept-version", "1.2"),
+            ("host", "/"),
+            ("heart-beat", heart_beat.as_str()),
+        ];
+        if let Some((user, pass)) = login {
+            headers.extend([("login", user), ("passcode", pass)]);
+        }
+        sub.send("CONNECT", &headers)
+            .await
+            .map_err(|e| failed(&e))?;
+        let connected = sub.read_frame().await.map_err(|e| failed(&e))?;
+        match connected.command.as_str() {
+            "CONNECTED" => {}
+            "ERROR" => return Err(failed(&connected.error_message())),
+            other => return Err(failed(&format!("unexpected {other} frame"))),
+        }
+        sub.read_timeout = connected.heart_beat_timeout();
+        sub.send(
+            "SUBSCRIBE",
+            &[
+                ("id", "0"),
+                ("destination", DESTINATION),
+                ("ack", "auto"),
+                ("receipt", SUBSCRIBE_RECEIPT),
+            ],
+        )
+        .await
+        .map_err(|e| failed(&e))?;
+        loop {
+            let frame = sub.read_frame().await.map_err(|e| failed(&e))?;
+            match frame.command.as_str() {
+                "RECEIPT" if frame.header("receipt-id") == Some(SUBSCRIBE_RECEIPT) => break,
+                "ERROR" => {
+                    return Err(BcliError::EventBus(format!(
+                        "Broker at {broker} refused subscription to {DESTINATION}: {}",
+                        frame.error_message()
+                    )));
+                }
+                "MESSAGE" => sub.pending.push_back(frame),
+                _ => {}
+            }
+        }
+        Ok(sub)
+    }
+
+    /// The destination and body of the next message
+    async fn receive(&mut self) -> Result<(String, Vec<u8>)> {
+        loop {
+            let frame = match self.pending.pop_front() {
+                Some(frame) => frame,
+                None => self.read_frame().await.map_err(|e| {
+                    BcliError::EventBus(format!(
+                        "Lost connection to broker at {}: {e}",
+                        self.broker
+                    ))
+                })?,
+            };
+            match frame.command.as_str() {
+                "MESSAGE" => {
+                    let destination = frame.header("destination").unwrap_or(DESTINATION);
+                    return Ok((destination.to_owned(), frame.body));
+                }
+                "ERROR" => {
+                    return Err(BcliError::EventBus(format!(
+                        "Broker at {} reported an error: {}",
+                        self.broker,
+                        frame.error_message()
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Send a frame without a body
+    ///
+    /// Header values are not escaped so must not contain newlines, or `:` or
+    /// `\` other than in a CONNECT frame (which is never escaped).
+    async fn send(&mut self, command: &str, headers: &[(&str, &str)]) -> io::Result<()> {
+        let mut frame = format!("{command}\n");
+        for (name, value) in headers {
+            let _ = writeln!(frame, "{name}:{value}");
+        }
+        frame.push_str("\n\0");
+        self.stream.get_mut().write_all(frame.as_bytes()).await
+    }
+
+    async fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut line = String::new();
+        // Frames can be separated by any number of newlines (heart-beats)
+        let command = loop {
+            self.read_line(&mut line).await?;
+            if !line.is_empty() {
+                break line.clone();
+            }
+        };
+        let mut headers = Vec::new();
+        loop {
+            self.read_line(&mut line).await?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid header '{line}'"),
+                )
+            })?;
+            headers.push((unescape(name), unescape(value)));
+        }
+        let mut frame = Frame {
+            command,
+            headers,
+            body: Vec::new(),
+        };
+        let limit = self.read_timeout;
+        match frame.header("content-length").map(str::parse::<usize>) {
+            Some(Ok(len)) => {
+                // The body is followed by a NUL
+                let size = len
+                    .checked_add(1)
+                    .filter(|size| *size <= MAX_BODY)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("content-length {len} is over the {MAX_BODY} byte limit"),
+                        )
+                    })?;
+                frame.body = vec![0; size];
+                within(limit, self.stream.read_exact(&mut frame.body)).await?;
+            }
+            Some(Err(_)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid content-length",
+                ));
+            }
+            None => {
+                within(limit, self.stream.read_until(0, &mut frame.body)).await?;
+                // Without a NUL the connection closed partway through the body
+                if frame.body.last() != Some(&0) {
+                    return Err(closed());
+                }
+            }
+        }
+        match frame.body.pop() {
+            Some(0) => Ok(frame),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame was not terminated",
+            )),
+            None => Err(closed()),
+        }
+    }
+
+    /// Read a line into the buffer, without its line ending
+    async fn read_line(&mut self, line: &mut String) -> io::Result<()> {
+        line.clear();
+        if within(self.read_timeout, self.stream.read_line(line)).await? == 0 {
+            return Err(closed());
+        }
+        let len = line.trim_end_matches(['\r', '\n']).len();
+        line.truncate(len);
+        Ok(())
+    }
+}
+
+impl Frame {
+    /// The value of a header - if it is repeated the first one is used
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The reason given in an ERROR frame
+    fn error_message(&self) -> String {
+        let body = String::from_utf8_lossy(&self.body);
+        match (self.header("message"), body.trim()) {
+            (Some(message), "") => message.to_owned(),
+            (Some(message), detail) => format!("{message} ({detail})"),
+            (None, "") => "no reason given".into(),
+            (None, detail) => detail.to_owned(),
+        }
+    }
+
+    /// How long to wait for the broker's heart-beats, agreed in a CONNECTED
+    /// frame, before deciding that the connection has gone
+    ///
+    /// The broker sends them at the larger of its own interval and the one
+    /// asked for, and twice that is allowed for them to arrive.
+    fn heart_beat_timeout(&self) -> Option<Duration> {
+        // heart-beat:<broker sends>,<broker wants>
+        let (broker, _) = self.header("heart-beat")?.split_once(',')?;
+        let broker = broker.trim().parse::<u64>().ok().filter(|&ms| ms > 0)?;
+        Some(Duration::from_millis(broker).max(HEART_BEAT) * 2)
+    }
+}
+
+/// Wait for a read, failing if nothing arrives within the limit
+async fn within<T>(
+    limit: Option<Duration>,
+    read: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match limit {
+        Some(limit) => time::timeout(limit, read).await.unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no heart-beat from broker for {}s", limit.as_secs()),
+            ))
+        }),
+        None => read.await,
+    }
+}
+
+fn connect_failed(broker: &StompAddress, e: &dyn std::fmt::Display) -> BcliError {
+    BcliError::EventBus(format!("Couldn't connect to broker at {broker}: {e}"))
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by broker")
+}
+
+/// Undo the escaping of `:`, `\` and newlines in header names and values
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('c') => unescaped.push(':'),
+            Some('\\') => unescaped.push('\\'),
+            // Other escapes are not valid but are kept as they are
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+    use tokio::time;
+
+    use super::{DESTINATION, StompSubscription, unescape};
+    use crate::error::Result;
+
+    fn frame(command: &str, headers: &[(&str, &str)], body: &str) -> String {
+        let mut frame = format!("{command}\n");
+        for (name, value) in headers {
+            frame.push_str(&format!("{name}:{value}\n"));
+        }
+        format!("{frame}\n{body}\0")
+    }
+
+    fn connected(heart_beat: &str) -> String {
+        frame(
+            "CONNECTED",
+            &[("version", "1.2"), ("heart-beat", heart_beat)],
+            "",
+        )
+    }
+
+    fn receipt() -> String {
+        frame("RECEIPT", &[("receipt-id", "bcli-subscribe")], "")
+    }
+
+    fn message(body: &str) -> String {
+        frame("MESSAGE", &[("destination", DESTINATION)], body)
+    }
+
+    /// Run the handshake against a broker that has already sent `replies`
+    async fn subscribe(
+        replies: &str,
+        login: Option<(&str, &str)>,
+    ) -> (Result<StompSubscription<DuplexStream>>, DuplexStream) {
+        let (client, mut broker) = duplex(64 * 1024);
+        broker
+            .write_all(replies.as_bytes())
+            .await
+            .expect("writing to duplex");
+        let broker_address = "broker".parse().expect("valid address");
+        let sub = StompSubscription::handshake(client, &broker_address, login).await;
+        (sub, broker)
+    }
+
+    /// Everything the client has sent so far
+    async fn sent(broker: &mut DuplexStream) -> String {
+        let mut sent = Vec::new();
+        let mut buf = [0; 1024];
+        while let Ok(Ok(n @ 1..)) =
+            time::timeout(Duration::from_millis(50), broker.read(&mut buf)).await
+        {
+            sent.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(sent).expect("frames are utf-8")
+    }
+
+    fn error<T>(result: Result<T>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let replies = connected("0,0") + &receipt();
+        let (sub, mut broker) = subscribe(&replies, Some(("user", "pa:ss"))).await;
+        let sub = sub.expect("handshake succeeds");
+        assert_eq!(sub.read_timeout, None);
+        assert_eq!(
+            sent(&mut broker).await,
+            "CONNECT\naccept-version:1.2\nhost:/\nheart-beat:0,10000\n\
+             login:user\npasscode:pa:ss\n\n\0\
+             SUBSCRIBE\nid:0\ndestination:/topic/public.worker.event\n\
+             ack:auto\nreceipt:bcli-subscribe\n\n\0"
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_without_login() {
+        let replies = connected("0,0") + &receipt();
+        let (sub, mut broker) = subscribe(&replies, None).await;
+        assert!(sub.is_ok());
+        assert!(!sent(&mut broker).await.contains("login"));
+    }
+
+    #[tokio::test]
+    async fn messages_before_receipt_are_kept() {
+        let replies = connected("0,0") + &message("first") + &receipt() + &message("second");
+        let (sub, _broker) = subscribe(&replies, None).await;
+        let mut sub = sub.expect("handshake succeeds");
+        for expected in ["first", "second"] {
+            let (destination, body) = sub.receive().await.expect("message received");
+            assert_eq!(destination, DESTINATION);
+            assert_eq!(body, expected.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn framing() {
+        let replies = connected("0,0")
+            + &receipt()
+            // Heart-beats between frames
+            + "\n\r\n\n"
+            // A body containing NULs needs a content-length
+            + &frame(
+                "MESSAGE",
+                &[("destination", DESTINATION), ("content-length", "5")],
+                "a\0b\nc",
+            )
+            // Escaped headers and CRLF line endings
+            + "MESSAGE\r\ndestination:/topic/a\\cb\\\\c\r\n\r\nbody\0"
+            // Frames other than MESSAGE are skipped
+            + &frame("RECEIPT", &[("receipt-id", "other")], "")
+            + &message("");
+        let (sub, _broker) = subscribe(&replies, None).await;
+        let mut sub = sub.expect("handshake succeeds");
+        let (_, body) = sub.receive().await.expect("message received");
+        assert_eq!(body, b"a\0b\nc");
+        let (destination, body) = sub.receive().await.expect("message received");
+        assert_eq!(destination, "/topic/a:b\\c");
+        assert_eq!(body, b"body");
+        let (_, body) = sub.receive().await.expect("message received");
+        assert_eq!(body, b"");
+    }
+
+    #[tokio::test]
+    async fn connection_refused() {
+        let replies = frame("ERROR", &[("message", "Access refused")], "bad login\n");
+        let (sub, _broker) = subscribe(&replies, None).await;
+        assert_eq!(
+            error(sub),
+            "Event bus error: Couldn't connect to broker at broker:61613: \
+             Access refused (bad login)"
+        );
+    }
+
+    #[tokio::test]
+    async fn subscription_refused() {
+        let replies = connected("0,0") + &frame("ERROR", &[("message", "Not allowed")], "");
+        let (sub, _broker) = subscribe(&replies, None).await;
+        assert_eq!(
+            error(sub),
+            "Event bus error: Broker at broker:61613 refused subscription to \
+             /topic/public.worker.event: Not allowed"
+        );
+    }
+
+    #[tokio::test]
+    async fn error_after_subscribing() {
+        let replies = connected("0,0") + &receipt() + &frame("ERROR", &[], "shutting down");
+        let (sub, _broker) = subscribe(&replies, None).await;
+        let mut sub = sub.expect("handshake succeeds");
+        assert_eq!(
+            error(sub.receive().await),
+            "Event bus error: Broker at broker:61613 reported an error: shutting down"
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_closed() {
+        let replies = connected("0,0") + &receipt() + "MESSAGE\ndestination:x\n\npart";
+        let (sub, broker) = subscribe(&replies, None).await;
+        let mut sub = sub.expect("handshake succeeds");
+        drop(broker);
+        assert_eq!(
+            error(sub.receive().await),
+            "Event bus error: Lost connection to broker at broker:61613: \
+             connection closed by broker"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_frames() {
+        for (message, reason) in [
+            (
+                "MESSAGE\ncontent-length:2\n\nabc\0",
+                "frame was not terminated",
+            ),
+            (
+                "MESSAGE\ncontent-length:two\n\nab\0",
+                "invalid content-length",
+            ),
+            ("MESSAGE\nno-colon\n\n\0", "invalid header 'no-colon'"),
+        ] {
+            let replies = connected("0,0") + &receipt() + message;
+            let (sub, _broker) = subscribe(&replies, None).await;
+            let mut sub = sub.expect("handshake succeeds");
+            assert!(error(sub.receive().await).ends_with(reason), "{message}");
+        }
+        // Lengths too large to allocate are refused before reading the body
+        for len in [usize::MAX.to_string(), "1000000000".into()] {
+            let message = format!("MESSAGE\ncontent-length:{len}\n\nab\0");
+            let replies = connected("0,0") + &receipt() + &message;
+            let (sub, _broker) = subscribe(&replies, None).await;
+            let mut sub = sub.expect("handshake succeeds");
+            let err = error(sub.receive().await);
+            assert!(err.ends_with("byte limit"), "{err}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heart_beats() {
+        // The broker sends heart-beats every 10s as asked, so the connection is
+        // given up on after 20s without one
+        let replies = connected("5000,0") + &receipt();
+        let (sub, mut broker) = subscribe(&replies, None).await;
+        let mut sub = sub.expect("handshake succeeds");
+        assert_eq!(sub.read_timeout, Some(Duration::from_secs(20)));
+        let beats = tokio::spawn(async move {
+            for _ in 0..6 {
+                time::sleep(Duration::from_secs(10)).await;
+                broker.write_all(b"\n").await.expect("writing to duplex");
+            }
+            broker
+                .write_all(message("late").as_bytes())
+                .await
+                .expect("writing to duplex");
+            broker
+        });
+        let (_, body) = sub.receive().await.expect("heart-beats keep it alive");
+        assert_eq!(body, b"late");
+        let _broker = beats.await.expect("heart-beats sent");
+        assert_eq!(
+            error(sub.receive().await),
+            "Event bus error: Lost connection to broker at broker:61613: \
+             no heart-beat from broker for 20s"
+        );
+    }
+
+    #[tokio::test]
+    async fn broker_without_heart_beats() {
+        for heart_beat in ["0,0", "0,5000"] {
+            let replies = connected(heart_beat) + &receipt();
+            let (sub, _broker) = subscribe(&replies, None).await;
+            assert_eq!(sub.expect("handshake succeeds").read_timeout, None);
+        }
+        let replies = connected("30000,0") + &receipt();
+        let (sub, _broker) = subscribe(&replies, None).await;
+        assert_eq!(
+            sub.expect("handshake succeeds").read_timeout,
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn unescaping() {
+        assert_eq!(unescape(r"a\cb\\c\nd\re"), "a:b\\c\nd\re");
+        assert_eq!(unescape(r"bad\t"), r"bad\t");
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+    }
+}
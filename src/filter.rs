@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use uuid::Uuid;
+
+use crate::cli::ListenFilter;
+use crate::messages::Message;
+use crate::messages::data_model::EventDocument;
+
+/// The kinds of [`Message`] published by blueapi
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MessageKind {
+    /// Progress of the statuses being watched by a plan
+    Progress,
+    /// Changes to the state of the worker and its task
+    Worker,
+    /// Documents produced by runs
+    Data,
+}
+
+/// The kinds of [`EventDocument`] produced by a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DocKind {
+    Start,
+    Descriptor,
+    Event,
+    EventPage,
+    Stop,
+    Datum,
+    DatumPage,
+    Resource,
+    StreamResource,
+    StreamDatum,
+}
+
+/// Decides which messages `listen` shows
+///
+/// Documents other than start and stop documents don't say which run they are
+/// part of directly, so the descriptors and resources of each run are
+/// remembered to find the run of the documents that refer to them. They are
+/// forgotten once the run stops.
+pub struct MessageFilter {
+    options: ListenFilter,
+    /// The run each descriptor, resource and stream resource belongs to
+    runs: HashMap<Uuid, Uuid>,
+    /// Runs whose start document had the scan ID being filtered for
+    scans: HashSet<Uuid>,
+}
+
+impl MessageFilter {
+    pub fn new(options: ListenFilter) -> Self {
+        Self {
+            options,
+            runs: HashMap::new(),
+            scans: HashSet::new(),
+        }
+    }
+
+    /// Whether a message should be shown
+    pub fn matches(&mut self, msg: &Message) -> bool {
+        let run = match msg {
+            Message::Data { event, .. } => self.run_of(event),
+            _ => None,
+        };
+        let options = &self.options;
+        let matched = options.task.is_none_or(|task| msg.task_id() == Some(task))
+            && options.kind.is_none_or(|kind| kind == MessageKind::of(msg))
+            && match msg {
+                Message::Data { event, .. } => {
+                    (options.doc.is_empty() || options.doc.contains(&DocKind::of(event)))
+                        && options.run.is_none_or(|uid| run == Some(uid))
+                        && options
+                            .scan_id
+                            .is_none_or(|_| run.is_some_and(|uid| self.scans.contains(&uid)))
+                }
+                _ => options.doc.is_empty() && options.run.is_none() && options.scan_id.is_none(),
+            };
+        // Forget stopped runs whether or not their stop document is shown
+        if let Message::Data {
+            event: EventDocument::Stop(stop),
+            ..
+        } = msg
+        {
+            self.forget(stop.run_start);
+        }
+        matched
+    }
+
+    /// Forget the descriptors and resources of a run that has stopped
+    fn forget(&mut self, run: Uuid) {
+        self.runs.retain(|_, owner| *owner != run);
+        self.scans.remove(&run);
+    }
+
+    /// The UID of the start document of the run a document is part of, if
+    /// it's known
+    fn run_of(&mut self, doc: &EventDocument) -> Option<Uuid> {
+        match doc {
+            EventDocument::Start(start) => {
+                if self.options.scan_id.is_some() && start.scan_id == self.options.scan_id {
+                    self.scans.insert(start.uid);
+                }
+                Some(start.uid)
+            }
+            EventDocument::Stop(stop) => Some(stop.run_start),
+            EventDocument::Descriptor(desc) => {
+                self.runs.insert(desc.uid, desc.run_start);
+                Some(desc.run_start)
+            }
+            EventDocument::Resource(res) => {
+                let run = res.run_start?;
+                self.runs.insert(res.uid, run);
+                Some(run)
+            }
+            EventDocument::StreamResource(res) => {
+                let run = res.run_start?;
+                self.runs.insert(res.uid, run);
+                Some(run)
+            }
+            EventDocument::Event(event) => self.runs.get(&event.descriptor).copied(),
+            EventDocument::EventPage(page) => self.runs.get(&page.descriptor).copied(),
+            EventDocument::Datum(datum) => self.runs.get(&datum.resource).copied(),
+            EventDocument::DatumPage(page) => self.runs.get(&page.resource).copied(),
+            EventDocument::StreamDatum(datum) => self.runs.get(&datum.descriptor).copied(),
+        }
+    }
+}
+
+impl MessageKind {
+    fn of(msg: &Message) -> Self {
+        match msg {
+            Message::Progress(_) => MessageKind::Progress,
+            Message::Worker(_) => MessageKind::Worker,
+            Message::Data { .. } => MessageKind::Data,
+        }
+    }
+}
+
+impl DocKind {
+    fn of(doc: &EventDocument) -> Self {
+        match doc {
+            EventDocument::Start(_) => DocKind::Start,
+            EventDocument::Descriptor(_) => DocKind::Descriptor,
+            EventDocument::Event(_) => DocKind::Event,
+            EventDocument::EventPage(_) => DocKind::EventPage,
+            EventDocument::Stop(_) => DocKind::Stop,
+            EventDocument::Datum(_) => DocKind::Datum,
+            EventDocument::DatumPage(_) => DocKind::DatumPage,
+            EventDocument::Resource(_) => DocKind::Resource,
+            EventDocument::StreamResource(_) => DocKind::StreamResource,
+            EventDocument::StreamDatum(_) => DocKind::StreamDatum,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::{MessageFilter, MessageKind};
+    use crate::cli::ListenFilter;
+    use crate::messages::Message;
+
+    const TASK: &str = "7e6a3c1f-1111-4222-8333-444455556666";
+    const RUN: &str = "aaaaaaaa-1111-4222-8333-444455556666";
+    const DESCRIPTOR: &str = "bbbbbbbb-1111-4222-8333-444455556666";
+
+    fn message(name: &str, doc: serde_json::Value) -> Message {
+        serde_json::from_value(json!({"task_id": TASK, "name": name, "doc": doc}))
+            .expect("valid message")
+    }
+
+    fn start(scan_id: u32) -> Message {
+        message(
+            "start",
+            json!({"uid": RUN, "time": 0.0, "scan_id": scan_id}),
+        )
+    }
+
+    fn descriptor() -> Message {
+        message(
+            "descriptor",
+            json!({"uid": DESCRIPTOR, "run_start": RUN, "time": 0.0, "name": "primary", "data_keys": {}}),
+        )
+    }
+
+    fn event(seq_num: u64) -> Message {
+        message(
+            "event",
+            json!({
+                "uid": Uuid::new_v4(),
+                "time": 0.0,
+                "descriptor": DESCRIPTOR,
+                "seq_num": seq_num,
+                "data": {},
+                "timestamps": {},
+            }),
+        )
+    }
+
+    fn stop() -> Message {
+        message(
+            "stop",
+            json!({"uid": Uuid::new_v4(), "run_start": RUN, "time": 0.0, "exit_status": "success"}),
+        )
+    }
+
+    fn by_scan(scan_id: u32) -> MessageFilter {
+        MessageFilter::new(ListenFilter {
+            task: None,
+            kind: None,
+            doc: Vec::new(),
+            run: None,
+            scan_id: Some(scan_id),
+        })
+    }
+
+    fn by_kind(kind: MessageKind) -> MessageFilter {
+        MessageFilter::new(ListenFilter {
+            task: None,
+            kind: Some(kind),
+            doc: Vec::new(),
+            run: None,
+            scan_id: None,
+        })
+    }
+
+    #[test]
+    fn matches_documents_of_scan() {
+        let mut filter = by_scan(7);
+        for msg in [start(7), descriptor(), event(1), stop()] {
+            assert!(filter.matches(&msg), "{msg}");
+        }
+        let mut filter = by_scan(8);
+        for msg in [start(7), descriptor(), event(1), stop()] {
+            assert!(!filter.matches(&msg), "{msg}");
+        }
+    }
+
+    #[test]
+    fn forgets_run_once_stopped() {
+        let mut filter = by_scan(7);
+        for msg in [start(7), descriptor(), event(1), stop()] {
+            filter.matches(&msg);
+        }
+        assert!(filter.runs.is_empty());
+        assert!(filter.scans.is_empty());
+        // Late events can no longer be matched to the run
+        assert!(!filter.matches(&event(2)));
+    }
+
+    #[test]
+    fn forgets_run_when_stop_is_not_shown() {
+        let mut filter = by_kind(MessageKind::Worker);
+        for msg in [start(7), descriptor(), event(1), stop()] {
+            assert!(!filter.matches(&msg), "{msg}");
+        }
+        assert!(filter.runs.is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use std::process::Command;
+
+use crate::config::ConfigError;
+use crate::error::{BcliError, Result};
+
+/// Name that bcli's passwords are stored under in the keyring
+const SERVICE: &str = "bcli";
+
+/// Look up a password stored in the user's keyring
+///
+/// On macOS this is the login keychain (via `security`), elsewhere it is the
+/// Secret Service (via `secret-tool`, eg GNOME Keyring or KWallet). Passwords
+/// are found by the account they were stored with, eg:
+///
+/// ```sh
+/// secret-tool store --label "bcli" service bcli account user@broker
+/// security add-generic-password -s bcli -a user@broker -w
+/// ```
+///
+/// Returns `None` if there is no matching password or no keyring available.
+pub fn password(account: &str) -> Option<String> {
+    let output = match cfg!(target_os = "macos") {
+        true => Command::new("security")
+            .args(["find-generic-password", "-s", SERVICE, "-a", account, "-w"])
+            .output(),
+        false => Command::new("secret-tool")
+            .args(["lookup", "service", SERVICE, "account", account])
+            .output(),
+    };
+    let output = output.ok().filter(|out| out.status.success())?;
+    let password = String::from_utf8(output.stdout).ok()?;
+    // security ends the password with a newline, secret-tool doesn't
+    let password = password.strip_suffix('\n').unwrap_or(&password);
+    (!password.is_empty()).then(|| password.to_owned())
+}
+
+/// The password for a broker account, from the environment variable `var` or
+/// the keyring entry for `<username>@<host>`
+///
+/// Setting the variable to an empty string connects with an empty password.
+pub async fn broker_password(var: &'static str, username: &str, host: &str) -> Result<String> {
+    let account = format!("{username}@{host}");
+    let password = match std::env::var(var) {
+        Ok(password) => Some(password),
+        // The keyring is read by running a program so keep it off the
+        // runtime's threads
+        Err(_) => {
+            let account = account.clone();
+            tokio::task::spawn_blocking(move || password(&account))
+                .await
+                .ok()
+                .flatten()
+        }
+    };
+    password.ok_or(BcliError::Config(ConfigError::MissingPassword {
+        account,
+        var,
+    }))
+}
@@ -1,296 +1,819 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::{Duration, Instant};
 
-use clap::Parser;
-use cli::{CliArgs, RunArgs};
-use entities::{Device, DeviceList, PlanList, TaskReference};
-use messages::Message;
-use reqwest::Url;
-use rumqttc::{Event, MqttOptions, Packet, QoS};
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use cli::{Cli, CliArgs, ConnectionArgs, RunArgs, TaskCommand, WaitCondition};
+use entities::{
+    ActiveTask, Device, DeviceList, PlanList, PlanSpec, TaskId, TaskList, TaskReference,
+    TrackableTask,
+};
+use messages::data_model::EventDocument;
+use messages::{Message, TaskStatus, WorkerEvent};
+use reqwest::{RequestBuilder, Url};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 use tokio::runtime::Runtime;
-use tokio::sync::mpsc::{self, Receiver};
-use tokio::time;
-use uuid::Uuid;
+use tokio::time::{self, MissedTickBehavior};
 
-use crate::cli::PackageFilter;
+use crate::callbacks::Callback;
+use crate::cli::{ListenFilter, PackageFilter, SaveArgs};
+use crate::config::{ConfigFile, ServerConfig};
 use crate::entities::{EnvironmentState, NewState, PythonEnvironment, WorkerState};
+use crate::error::{BcliError, Result};
+use crate::events::{EventStream, Recorder, SharedEvents, Speed};
+use crate::filter::MessageFilter;
+use crate::monitor::Monitor;
+use crate::output::OutputFormat;
+use crate::params::PlanFlags;
+use crate::progress::Printer;
+use crate::watch::StateWatch;
 
+mod callbacks;
 mod cli;
+mod complete;
+mod config;
 mod entities;
+mod error;
+mod events;
+mod filter;
+mod keyring;
 mod messages;
+mod monitor;
+mod output;
+mod params;
+mod progress;
+mod shell;
+mod watch;
 
-fn main() {
-    let args = CliArgs::parse();
+/// How often to check the server when waiting for something without events
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
 
-    let client = reqwest::Client::new();
-    let client = Client {
-        agent: client,
-        host: Url::parse("http://localhost:8000").unwrap(),
-        mqtt: ("localhost".into(), 1883),
-    };
+fn main() -> ExitCode {
+    // Respond to completion requests from the shell (and exit) if this is one
+    CompleteEnv::with_factory(Cli::command)
+        .var(complete::COMPLETE_VAR)
+        .complete();
+    let Cli {
+        connection,
+        output,
+        command,
+    } = Cli::parse();
+
+    match run(connection, output, command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            e.exit_code()
+        }
+    }
+}
+
+fn run(connection: ConnectionArgs, output: OutputFormat, command: CliArgs) -> Result<()> {
+    // Completion scripts don't depend on the server (or a valid config)
+    if let CliArgs::Completions { shell } = command {
+        return complete::print_registration(shell);
+    }
+    let server = ConfigFile::load(connection.config.as_deref())?.resolve(&connection)?;
+    let client = Client::new(server, output);
 
     let rt = Runtime::new().expect("Couldn't create runtime");
     rt.block_on(async {
-        match args {
-            CliArgs::Run(run_args) => client.run_plan(run_args).await,
-            CliArgs::Devices { name: filter } => client.list_devices(filter).await.unwrap(),
-            CliArgs::Plans { name } => client.get_plans(name).await,
-            CliArgs::Pause { defer } => client.pause(defer).await,
-            CliArgs::Resume => client.resume().await,
-            CliArgs::Stop => client.stop().await,
-            CliArgs::Abort { reason } => client.abort(reason).await,
-            CliArgs::State => client.state().await,
-            CliArgs::Env { reload, timeout } => match reload {
-                true => client.reload_env(timeout).await,
-                false => println!("{:?}", client.get_env().await),
-            },
-            CliArgs::GetPythonEnv(filter) => client.get_python_env(filter).await,
-            CliArgs::Listen => client.listen().await,
+        match command {
+            CliArgs::Shell => shell::run(client).await,
+            command => client.execute(command).await,
         }
-    });
+    })
 }
 
+#[derive(Clone)]
 struct Client {
     agent: reqwest::Client,
-    host: Url,
-    mqtt: (String, u16),
+    server: ServerConfig,
+    output: OutputFormat,
+    /// Subscription to reuse for every command instead of subscribing each
+    /// time events are needed
+    events: Option<SharedEvents>,
 }
 
 impl Client {
-    async fn run_plan(&self, args: RunArgs) {
-        let req = self
-            .agent
-            .post(self.endpoint("/tasks"))
-            .json(&HashMap::from([
-                ("name".to_owned(), Value::String(args.name().into())),
-                ("params".to_owned(), args.parameters().unwrap().unwrap()),
-                ("instrument_session".into(), args.instrument_session()),
-            ]))
-            .send()
-            .await;
-        let task = req.unwrap().json::<TaskReference>().await.unwrap();
-        let messages = args.foreground().then(|| self.message_stream());
-        let resp = self
-            .agent
-            .put(self.endpoint("/worker/task"))
-            .json(&task)
-            .send()
-            .await
-            .unwrap();
+    fn new(server: ServerConfig, output: OutputFormat) -> Self {
+        Self {
+            agent: reqwest::Client::new(),
+            server,
+            output,
+            events: None,
+        }
+    }
+
+    async fn execute(&self, command: CliArgs) -> Result<()> {
+        match command {
+            CliArgs::Run(run_args) => self.run_plan(run_args).await,
+            CliArgs::Tasks(command) => match command {
+                TaskCommand::List => self.list_tasks().await,
+                TaskCommand::Show { task_id } => self.show_task(task_id).await,
+                TaskCommand::Delete { task_id } => self.delete_task(task_id).await,
+                TaskCommand::Start {
+                    task_id,
+                    background,
+                    save,
+                    verbose,
+                } => {
+                    self.start_created_task(task_id, background, save, verbose)
+                        .await
+                }
+            },
+            CliArgs::Attach {
+                task_id,
+                save,
+                verbose,
+            } => self.attach(task_id, save, verbose).await,
+            CliArgs::Devices { name: filter } => self.list_devices(filter).await,
+            CliArgs::Plans { name } => self.get_plans(name).await,
+            CliArgs::Pause { defer } => self.pause(defer).await,
+            CliArgs::Resume => self.resume().await,
+            CliArgs::Stop => self.stop().await,
+            CliArgs::Abort { reason } => self.abort(reason).await,
+            CliArgs::State { watch, interval } => match watch {
+                true => self.watch_state(Duration::from_secs(interval)).await,
+                false => self.state().await,
+            },
+            CliArgs::Wait { condition, timeout } => self.wait(condition, timeout).await,
+            CliArgs::Env { reload, timeout } => match reload {
+                true => self.reload_env(timeout).await,
+                false => self.show_env().await,
+            },
+            CliArgs::GetPythonEnv(filter) => self.get_python_env(filter).await,
+            CliArgs::Listen {
+                filter,
+                save,
+                record,
+                verbose,
+            } => self.listen(filter, save, record, verbose).await,
+            CliArgs::Replay {
+                file,
+                speed,
+                save,
+                verbose,
+            } => self.replay(file, speed, save, verbose).await,
+            CliArgs::Completions { shell } => complete::print_registration(shell),
+            CliArgs::Shell => {
+                eprintln!("Already running a shell");
+                Ok(())
+            }
+        }
+    }
+
+    async fn run_plan(&self, args: RunArgs) -> Result<()> {
+        let mut run_cmd = Cli::command();
+        let run_cmd = run_cmd
+            .find_subcommand_mut("run")
+            .expect("run is a subcommand")
+            .clone();
+        let Some(name) = args.name() else {
+            println!("{}", run_cmd.bin_name("bcli run").render_help());
+            return Ok(());
+        };
+        let mut params = args.parameters()?;
+        // The schema is only needed if parameters are given as flags or are
+        // being validated
+        let plan = match args.help() || !args.flags().is_empty() || !args.no_validate {
+            true => Some(self.get_plan(name).await?),
+            false => None,
+        };
+        if let Some(plan) = &plan {
+            let flags = PlanFlags::new(&plan.schema);
+            if args.help() {
+                println!("{}", flags.help(run_cmd, plan));
+                return Ok(());
+            }
+            params.extend(flags.parse(&plan.name, args.flags())?);
+        }
+        let params = Value::Object(params);
+        if let Some(plan) = &plan
+            && !args.no_validate
+        {
+            params::validate(&plan.name, &plan.schema, &params)?;
+        }
+        let printer = Printer::default();
+        let export = args.save.exporter(&printer)?;
+        // Subscribe before the task is started so that none of its events are missed
+        let messages = match args.foreground() {
+            true => Some(self.message_stream(None).await?),
+            false => None,
+        };
+        let task = self
+            .post::<_, TaskReference>(
+                self.endpoint(&["tasks"])?,
+                &HashMap::from([
+                    ("name".to_owned(), Value::String(name.into())),
+                    ("params".to_owned(), params),
+                    ("instrument_session".into(), args.instrument_session()),
+                ]),
+            )
+            .await?;
+        let monitor = Monitor::new(printer, export, args.verbose);
+        self.start_task(task.task_id, messages, monitor).await
+    }
+
+    /// Set a task running and follow it until complete if there is a message
+    /// stream to follow it with
+    async fn start_task(
+        &self,
+        task_id: TaskId,
+        messages: Option<EventStream>,
+        monitor: Monitor,
+    ) -> Result<()> {
+        self.put::<_, Value>(
+            self.endpoint(&["worker", "task"])?,
+            &TaskReference { task_id },
+        )
+        .await?;
+        match messages {
+            Some(messages) => self.follow(task_id, messages, monitor).await,
+            None => {
+                println!("{task_id}");
+                Ok(())
+            }
+        }
+    }
+
+    async fn list_tasks(&self) -> Result<()> {
+        let tasks = self.get::<TaskList>(self.endpoint(&["tasks"])?).await?;
+        self.output.print(&tasks.tasks);
+        Ok(())
+    }
+
+    async fn show_task(&self, task_id: TaskId) -> Result<()> {
+        let task = self
+            .get::<TrackableTask>(self.endpoint(&["tasks", &task_id.to_string()])?)
+            .await?;
+        self.output.print(&task);
+        Ok(())
+    }
+
+    async fn delete_task(&self, task_id: TaskId) -> Result<()> {
+        let url = self.endpoint(&["tasks", &task_id.to_string()])?;
+        let deleted = self
+            .send::<TaskReference>(url.clone(), self.agent.delete(url))
+            .await?;
+        println!("{}", deleted.task_id);
+        Ok(())
+    }
+
+    async fn start_created_task(
+        &self,
+        task_id: TaskId,
+        background: bool,
+        save: SaveArgs,
+        verbose: bool,
+    ) -> Result<()> {
+        let printer = Printer::default();
+        let monitor = Monitor::new(printer.clone(), save.exporter(&printer)?, verbose);
+        let messages = match background {
+            true => None,
+            false => Some(self.message_stream(None).await?),
+        };
+        self.start_task(task_id, messages, monitor).await
+    }
+
+    async fn attach(&self, task_id: Option<TaskId>, save: SaveArgs, verbose: bool) -> Result<()> {
+        let printer = Printer::default();
+        let export = save.exporter(&printer)?;
+        // Subscribe before checking the task so that we can't miss it finishing
+        let messages = self.message_stream(None).await?;
+        let task_id = match task_id {
+            Some(id) => id,
+            None => self
+                .get::<ActiveTask>(self.endpoint(&["worker", "task"])?)
+                .await?
+                .task_id
+                .ok_or_else(|| BcliError::NoActiveTask)?,
+        };
+        let task = self
+            .get::<TrackableTask>(self.endpoint(&["tasks", &task_id.to_string()])?)
+            .await?;
+        if task.is_complete {
+            printer.println(format_args!("Task {task_id} has already finished"));
+            for error in &task.errors {
+                printer.println(format_args!("  Error: {error}"));
+            }
+            return match task.errors.is_empty() {
+                true => Ok(()),
+                false => Err(BcliError::TaskFailed(task_id)),
+            };
+        }
+        let monitor = Monitor::new(printer, export, verbose).attached();
+        self.follow(task_id, messages, monitor).await
+    }
 
-        if resp.status().is_success() {
-            if let Some(messages) = messages {
-                let mut messages = messages.await.unwrap().unwrap();
-                while let Some(msg) = messages.recv().await {
-                    if msg.task_id().is_none_or(|id| id != task.task_id) {
+    /// Display the messages for a task until it is complete
+    ///
+    /// If the connection to the event bus is interrupted, the task is checked
+    /// directly until it is restored so that its completion isn't missed.
+    async fn follow(
+        &self,
+        task_id: TaskId,
+        messages: EventStream,
+        mut monitor: Monitor,
+    ) -> Result<()> {
+        let mut messages = messages.with_printer(monitor.printer().clone());
+        let mut ticker = time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                msg = messages.recv() => {
+                    let Some(msg) = msg else {
+                        // The result of the task can't be known from the
+                        // events received so far so ask the server instead
+                        let event = self.polled_completion(task_id).await?.ok_or_else(|| {
+                            BcliError::EventBus(format!(
+                                "Event stream closed before task {task_id} completed"
+                            ))
+                        })?;
+                        monitor.handle(&Message::Worker(event));
+                        break;
+                    };
+                    if msg.task_id().is_none_or(|id| id != task_id) {
                         continue;
                     }
-                    match &msg {
-                        Message::Progress(_) => {}
-                        Message::Worker(worker_event) => {
-                            println!("{worker_event:#?}");
-                            if worker_event.complete() {
-                                break;
-                            }
-                        }
-                        Message::Data { event, .. } => println!("{event:#?}"),
+                    monitor.handle(&msg);
+                    if let Message::Worker(worker_event) = &msg
+                        && worker_event.complete()
+                    {
+                        break;
+                    }
+                }
+                _ = ticker.tick() => {
+                    if messages.interrupted()
+                        && let Ok(Some(event)) = self.polled_completion(task_id).await
+                    {
+                        monitor.handle(&Message::Worker(event));
+                        break;
                     }
                 }
             }
-        } else {
-            println!("{resp:?}");
-            println!("{:?}", resp.text().await);
         }
+        monitor.finish();
+        match monitor.outcome().failed() {
+            true => Err(BcliError::TaskFailed(task_id)),
+            false => Ok(()),
+        }
+    }
+
+    /// The worker event that would have been sent when the task completed, if
+    /// it has, built from the state of the worker and the task
+    async fn polled_completion(&self, task_id: TaskId) -> Result<Option<WorkerEvent>> {
+        let task = self
+            .get::<TrackableTask>(self.endpoint(&["tasks", &task_id.to_string()])?)
+            .await?;
+        if !task.is_complete {
+            return Ok(None);
+        }
+        Ok(Some(WorkerEvent {
+            state: self.worker_state().await?,
+            task_status: Some(TaskStatus {
+                task_id,
+                task_complete: true,
+                task_failed: !task.errors.is_empty(),
+            }),
+            errors: task.errors,
+            warnings: Vec::new(),
+        }))
     }
 
-    async fn list_devices(&self, name: Option<String>) -> Result<(), reqwest::Error> {
-        let devices = match name {
-            Some(name) => vec![
-                self.get::<Device>(self.endpoint(&format!("/devices/{name}")))
+    async fn list_devices(&self, name: Option<String>) -> Result<()> {
+        match name {
+            Some(name) => self.output.print(
+                &self
+                    .get::<Device>(self.endpoint(&["devices", &name])?)
                     .await?,
-            ],
-            None => self
-                .get::<DeviceList>(self.endpoint("/devices"))
-                .await?
-                .into_inner(),
-        };
-        for device in devices {
-            println!("{}", device);
+            ),
+            None => self.output.print(
+                &self
+                    .get::<DeviceList>(self.endpoint(&["devices"])?)
+                    .await?
+                    .into_inner(),
+            ),
         }
         Ok(())
     }
 
-    async fn get_plans(&self, name: Option<String>) {
-        let plans = match name {
-            Some(name) => vec![
-                self.get(self.endpoint(&format!("/plans/{name}")))
-                    .await
-                    .unwrap(),
-            ],
-            None => {
-                self.get::<PlanList>(self.endpoint("/plans"))
-                    .await
-                    .unwrap()
-                    .plans
+    async fn get_plan(&self, name: &str) -> Result<PlanSpec> {
+        self.get(self.endpoint(&["plans", name])?).await
+    }
+
+    async fn get_plans(&self, name: Option<String>) -> Result<()> {
+        match name {
+            Some(name) => self.output.print(&self.get_plan(&name).await?),
+            None => self.output.print(
+                &self
+                    .get::<PlanList>(self.endpoint(&["plans"])?)
+                    .await?
+                    .plans,
+            ),
+        }
+        Ok(())
+    }
+
+    async fn state(&self) -> Result<()> {
+        self.output.print(&self.worker_state().await?);
+        Ok(())
+    }
+
+    async fn worker_state(&self) -> Result<WorkerState> {
+        self.get(self.endpoint(&["worker", "state"])?).await
+    }
+
+    /// Print each change in the worker's state until interrupted
+    ///
+    /// Changes are taken from worker events where possible, falling back to
+    /// polling the server (which can't report errors and warnings) while the
+    /// event bus can't be reached.
+    async fn watch_state(&self, interval: Duration) -> Result<()> {
+        let mut watch = StateWatch::default();
+        // Subscribe before getting the initial state so no changes are missed
+        let mut messages = self.try_message_stream("the worker state").await;
+        let (state, task_id) = self.active_state().await?;
+        if let Some(change) = watch.update(state, task_id, &[], &[]) {
+            self.output.print_line(&change);
+        }
+        let mut ticker = time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut failing = false;
+        loop {
+            tokio::select! {
+                Some(msg) = next_message(&mut messages) => {
+                    if let Message::Worker(event) = msg
+                        && let Some(change) = watch.event(&event)
+                    {
+                        self.output.print_line(&change);
+                    }
+                }
+                _ = ticker.tick() => {
+                    if messages.as_mut().is_some_and(|m| !m.interrupted()) {
+                        continue;
+                    }
+                    match self.active_state().await {
+                        Ok((state, task_id)) => {
+                            if failing {
+                                eprintln!("Reconnected to {}", self.server.url);
+                                failing = false;
+                            }
+                            if let Some(change) = watch.update(state, task_id, &[], &[]) {
+                                self.output.print_line(&change);
+                            }
+                        }
+                        // Keep trying - the server might only be restarting - but
+                        // only report the problem once
+                        Err(e) if !failing => {
+                            eprintln!("Warning: {e}");
+                            failing = true;
+                        }
+                        Err(_) => {}
+                    }
+                }
             }
-        };
-        for plan in plans {
-            println!("{}", plan.name,);
-            println!("{}", plan.description.as_deref().unwrap_or("???"));
         }
     }
 
-    async fn state(&self) {
-        let state = self
-            .get::<WorkerState>(self.endpoint("/worker/state"))
+    /// Block until the condition is reached, failing if it isn't reached
+    /// within the timeout (in seconds)
+    async fn wait(&self, condition: WaitCondition, timeout: Option<u64>) -> Result<()> {
+        let waiting = async {
+            match &condition {
+                WaitCondition::Idle => self.wait_for_state(WorkerState::Idle).await,
+                WaitCondition::Paused => self.wait_for_state(WorkerState::Paused).await,
+                WaitCondition::EnvReady => self.wait_for_env().await,
+                WaitCondition::Task { task_id } => self.wait_for_task(*task_id).await,
+            }
+        };
+        let Some(timeout) = timeout else {
+            return waiting.await;
+        };
+        time::timeout(Duration::from_secs(timeout), waiting)
             .await
-            .unwrap();
-        println!("{state:?}")
+            .unwrap_or_else(|_| {
+                Err(BcliError::Timeout(match condition {
+                    WaitCondition::Idle => format!("Worker did not become idle within {timeout}s"),
+                    WaitCondition::Paused => {
+                        format!("Worker did not pause within {timeout}s")
+                    }
+                    WaitCondition::EnvReady => {
+                        format!("Environment was not ready within {timeout}s")
+                    }
+                    WaitCondition::Task { task_id } => {
+                        format!("Task {task_id} did not complete within {timeout}s")
+                    }
+                }))
+            })
+    }
+
+    async fn wait_for_state(&self, target: WorkerState) -> Result<()> {
+        let reached = |state| match state {
+            WorkerState::Panicked => Err(BcliError::WorkerPanicked),
+            state => Ok(state == target),
+        };
+        self.wait_until(
+            "the worker state",
+            |event| reached(event.state),
+            async || reached(self.worker_state().await?),
+        )
+        .await
+    }
+
+    async fn wait_for_task(&self, task_id: TaskId) -> Result<()> {
+        let url = self.endpoint(&["tasks", &task_id.to_string()])?;
+        self.wait_until(
+            "the task",
+            |event| match &event.task_status {
+                _ if event.state == WorkerState::Panicked => Err(BcliError::WorkerPanicked),
+                Some(status) if status.task_id == task_id && status.task_complete => {
+                    match status.task_failed || !event.errors.is_empty() {
+                        true => Err(BcliError::TaskFailed(task_id)),
+                        false => Ok(true),
+                    }
+                }
+                _ => Ok(false),
+            },
+            async || {
+                let task = self.get::<TrackableTask>(url.clone()).await?;
+                match (task.is_complete, task.errors.is_empty()) {
+                    (true, true) => Ok(true),
+                    (true, false) => Err(BcliError::TaskFailed(task_id)),
+                    (false, _) => match self.worker_state().await? {
+                        WorkerState::Panicked => Err(BcliError::WorkerPanicked),
+                        _ => Ok(false),
+                    },
+                }
+            },
+        )
+        .await
+    }
+
+    /// The environment isn't included in events so this always polls
+    async fn wait_for_env(&self) -> Result<()> {
+        loop {
+            let env = self.get_env().await?;
+            if let Some(msg) = env.error_message {
+                return Err(BcliError::Environment(msg));
+            }
+            if env.initialized {
+                return Ok(());
+            }
+            time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Wait until a check of worker events, or of the server if the event bus
+    /// can't be used or events could have been missed, returns true or fails
+    ///
+    /// The server is checked once after subscribing so that a condition that
+    /// has already been reached (or is reached before the first event) is not
+    /// missed.
+    async fn wait_until(
+        &self,
+        polling: &str,
+        on_event: impl Fn(&WorkerEvent) -> Result<bool>,
+        poll: impl AsyncFn() -> Result<bool>,
+    ) -> Result<()> {
+        let mut messages = self.try_message_stream(polling).await;
+        if poll().await? {
+            return Ok(());
+        }
+        let mut ticker = time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                Some(msg) = next_message(&mut messages) => {
+                    if let Message::Worker(event) = msg
+                        && on_event(&event)?
+                    {
+                        return Ok(());
+                    }
+                }
+                _ = ticker.tick() => {
+                    if messages.as_mut().is_none_or(EventStream::interrupted) && poll().await? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
     }
-    async fn pause(&self, defer: bool) {
+
+    /// The state of the worker and the task it is running
+    async fn active_state(&self) -> Result<(WorkerState, Option<TaskId>)> {
+        let state = self.worker_state().await?;
+        let task = self
+            .get::<ActiveTask>(self.endpoint(&["worker", "task"])?)
+            .await?;
+        Ok((state, task.task_id))
+    }
+
+    async fn pause(&self, defer: bool) -> Result<()> {
         self.set_state(WorkerState::Paused, None, Some(defer)).await
     }
 
-    async fn resume(&self) {
+    async fn resume(&self) -> Result<()> {
         self.set_state(WorkerState::Running, None, None).await
     }
 
-    async fn stop(&self) {
+    async fn stop(&self) -> Result<()> {
         self.set_state(WorkerState::Stopping, None, None).await
     }
 
-    async fn abort(&self, reason: Option<String>) {
+    async fn abort(&self, reason: Option<String>) -> Result<()> {
         self.set_state(WorkerState::Aborting, reason, None).await
     }
 
-    async fn set_state(&self, new_state: WorkerState, reason: Option<String>, defer: Option<bool>) {
+    async fn set_state(
+        &self,
+        new_state: WorkerState,
+        reason: Option<String>,
+        defer: Option<bool>,
+    ) -> Result<()> {
         self.put::<_, WorkerState>(
-            self.endpoint("/worker/state"),
+            self.endpoint(&["worker", "state"])?,
             &NewState {
                 new_state,
                 reason,
                 defer,
             },
         )
-        .await
-        .unwrap();
+        .await?;
+        Ok(())
+    }
+
+    async fn show_env(&self) -> Result<()> {
+        self.output.print(&self.get_env().await?);
+        Ok(())
     }
 
-    async fn get_env(&self) -> EnvironmentState {
-        self.get(self.endpoint("/environment")).await.unwrap()
+    async fn get_env(&self) -> Result<EnvironmentState> {
+        self.get(self.endpoint(&["environment"])?).await
     }
 
-    async fn reload_env(&self, timeout: Option<u64>) {
+    async fn reload_env(&self, timeout: Option<u64>) -> Result<()> {
+        let url = self.endpoint(&["environment"])?;
         let old = self
-            .agent
-            .delete(self.endpoint("/environment"))
-            .send()
-            .await
-            .unwrap()
-            .json::<EnvironmentState>()
-            .await
-            .unwrap();
-        let timeout = timeout.map(|t| Instant::now() + Duration::from_secs(t));
-        while timeout.is_none_or(|t| Instant::now() < t) {
-            let env = self.get_env().await;
+            .send::<EnvironmentState>(url.clone(), self.agent.delete(url))
+            .await?;
+        let deadline = timeout.map(|t| Instant::now() + Duration::from_secs(t));
+        while deadline.is_none_or(|t| Instant::now() < t) {
+            let env = self.get_env().await?;
             if let Some(msg) = env.error_message {
-                panic!("{msg}");
+                return Err(BcliError::Environment(msg));
             }
             if env.initialized && env.environment_id != old.environment_id {
-                println!("{env:?}");
-                break;
+                self.output.print(&env);
+                return Ok(());
             }
-            time::sleep(Duration::from_millis(500)).await;
+            time::sleep(POLL_INTERVAL).await;
         }
-        panic!("Timeout waiting for environment to reload")
+        Err(BcliError::Timeout(format!(
+            "Environment did not reload within {}s",
+            timeout.unwrap_or_default()
+        )))
     }
 
-    async fn get_python_env(&self, filter: PackageFilter) {
+    async fn get_python_env(&self, filter: PackageFilter) -> Result<()> {
+        let url = self.endpoint(&["python_environment"])?;
         let env = self
-            .agent
-            .get(self.endpoint("/python_environment"))
-            .query(&filter)
-            .send()
-            .await
-            .unwrap()
-            .json::<PythonEnvironment>()
-            .await
-            .unwrap();
-        println!("Scratch enabled: {}", env.scratch_enabled);
-        for pkg in env.installed_packages {
-            println!("- {}", pkg);
+            .send::<PythonEnvironment>(url.clone(), self.agent.get(url).query(&filter))
+            .await?;
+        self.output.print(&env);
+        Ok(())
+    }
+
+    async fn listen(
+        &self,
+        filter: ListenFilter,
+        save: SaveArgs,
+        record: Option<PathBuf>,
+        verbose: bool,
+    ) -> Result<()> {
+        let mut filter = MessageFilter::new(filter);
+        let mut export = save.exporter(&Printer::default())?;
+        let recorder = record.as_deref().map(Recorder::create).transpose()?;
+        let mut messages = self.message_stream(recorder).await?;
+        // The descriptors of the runs in progress, so events can be shown with
+        // the precision and units of their data
+        let mut descriptors = HashMap::new();
+        while let Some(msg) = messages.recv().await {
+            if let Message::Data { event, .. } = &msg {
+                // Filters only apply to what is printed so every run is saved
+                if let Some(export) = &mut export {
+                    export.document(event);
+                }
+                match event {
+                    EventDocument::Descriptor(desc) => {
+                        descriptors.insert(desc.uid, desc.clone());
+                    }
+                    EventDocument::Stop(stop) => {
+                        descriptors.retain(|_, desc| desc.run_start != stop.run_start)
+                    }
+                    _ => {}
+                }
+            }
+            if !filter.matches(&msg) {
+                continue;
+            }
+            match (&msg, verbose) {
+                (_, true) => println!("{msg:#?}"),
+                (
+                    Message::Data {
+                        event: EventDocument::Event(event),
+                        ..
+                    },
+                    false,
+                ) => println!("{}", event.described(descriptors.get(&event.descriptor))),
+                (_, false) => println!("{msg}"),
+            }
         }
+        Ok(())
     }
 
-    async fn listen(&self) {
-        let mut messages = self.message_stream().await.unwrap().unwrap();
+    async fn replay(
+        &self,
+        file: PathBuf,
+        speed: Speed,
+        save: SaveArgs,
+        verbose: bool,
+    ) -> Result<()> {
+        let printer = Printer::default();
+        let mut monitor = Monitor::new(printer.clone(), save.exporter(&printer)?, verbose);
+        let mut messages = events::replay(&file, speed)?.with_printer(printer);
         while let Some(msg) = messages.recv().await {
-            println!("{msg:?}");
+            monitor.handle(&msg);
         }
+        monitor.finish();
+        Ok(())
     }
 
-    async fn message_stream(&self) -> Option<Result<Receiver<Message>, ()>> {
-        let options = MqttOptions::new(
-            format!("bcli-{}", Uuid::new_v4()),
-            &self.mqtt.0,
-            self.mqtt.1,
-        );
+    async fn message_stream(&self, recorder: Option<Recorder>) -> Result<EventStream> {
+        match (&self.events, recorder) {
+            (Some(events), None) => Ok(events.stream()),
+            // Recordings need the raw payloads so use a subscription of their own
+            (_, recorder) => events::subscribe(&self.server, recorder).await,
+        }
+    }
 
-        let (client, mut conn) = rumqttc::AsyncClient::new(options, 10);
-        client
-            .subscribe("public/worker/event", QoS::AtMostOnce)
+    /// Subscribe to events if possible, otherwise warn that `polling` will be
+    /// polled instead
+    async fn try_message_stream(&self, polling: &str) -> Option<EventStream> {
+        self.message_stream(None)
             .await
-            .unwrap();
-        let (tx, rx) = mpsc::channel(10);
-        tokio::spawn(async move {
-            let tx = tx;
-            loop {
-                match conn.poll().await {
-                    Ok(Event::Incoming(Packet::Publish(data))) => {
-                        match serde_json::from_slice::<Message>(&data.payload) {
-                            Ok(evt) => {
-                                let closed = tx.send(evt).await.is_err();
-                                if closed {
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                println!("Err: {e}\n{}", String::from_utf8_lossy(&data.payload))
-                            }
-                        }
-                    }
-                    Ok(msg) => println!("Recv: {msg:?}"),
-                    Err(e) => println!("Error: {e:?}"),
-                }
-            }
-        });
+            .inspect_err(|e| eprintln!("Warning: {e}\nPolling {polling} instead"))
+            .ok()
+    }
 
-        Some(Ok(rx))
+    async fn get<T: DeserializeOwned>(&self, url: Url) -> Result<T> {
+        self.send(url.clone(), self.agent.get(url)).await
     }
 
-    async fn get<T: DeserializeOwned>(&self, url: Url) -> Result<T, reqwest::Error> {
-        self.agent.get(url).send().await.unwrap().json().await
+    async fn put<D: Serialize, T: DeserializeOwned>(&self, url: Url, data: &D) -> Result<T> {
+        self.send(url.clone(), self.agent.put(url).json(data)).await
     }
 
-    async fn put<D: Serialize, T: DeserializeOwned>(
-        &self,
-        url: Url,
-        data: &D,
-    ) -> Result<T, reqwest::Error> {
-        self.agent
-            .put(url)
-            .json(data)
+    async fn post<D: Serialize, T: DeserializeOwned>(&self, url: Url, data: &D) -> Result<T> {
+        self.send(url.clone(), self.agent.post(url).json(data))
+            .await
+    }
+
+    /// Send a request and deserialize the response, converting unsuccessful
+    /// responses into errors
+    async fn send<T: DeserializeOwned>(&self, url: Url, req: RequestBuilder) -> Result<T> {
+        let resp = req
             .send()
             .await
-            .unwrap()
-            .json()
+            .map_err(|e| BcliError::Transport(url.clone(), e))?;
+        let status = resp.status();
+        let body = resp
+            .bytes()
             .await
+            .map_err(|e| BcliError::Transport(url.clone(), e))?;
+        if !status.is_success() {
+            return Err(BcliError::status(
+                url,
+                status,
+                &String::from_utf8_lossy(&body),
+            ));
+        }
+        serde_json::from_slice(&body).map_err(|e| BcliError::Deserialize(url, e))
     }
 
-    fn endpoint(&self, path: &str) -> Url {
-        self.host.join(path).unwrap()
+    fn endpoint(&self, segments: &[&str]) -> Result<Url> {
+        self.server.endpoint(segments).map_err(BcliError::Config)
+    }
+}
+
+/// The next message from a stream, if there is one, for use in `select!`
+async fn next_message(messages: &mut Option<EventStream>) -> Option<Message> {
+    match messages {
+        Some(messages) => messages.recv().await,
+        None => None,
     }
 }
@@ -1,15 +1,16 @@
 #![allow(unused)]
 use std::collections::HashMap;
+use std::fmt::Display;
 
-use data_model::EventDocument;
+use data_model::{EventDocument, format_number};
 use serde::Deserialize;
 use uuid::Uuid;
 
 use crate::entities::{TaskId, WorkerState};
 
-mod data_model;
+pub mod data_model;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
 pub enum Message {
     Progress(ProgressEvent),
@@ -30,35 +31,35 @@ impl Message {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ProgressEvent {
-    task_id: TaskId,
-    statuses: HashMap<String, StatusView>,
+    pub task_id: TaskId,
+    pub statuses: HashMap<String, StatusView>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct StatusView {
-    display_name: String,
-    current: Option<f64>,
-    initial: Option<f64>,
-    target: Option<f64>,
-    unit: Option<String>,
-    precision: Option<i32>,
+    pub display_name: String,
+    pub current: Option<f64>,
+    pub initial: Option<f64>,
+    pub target: Option<f64>,
+    pub unit: Option<String>,
+    pub precision: Option<i32>,
     #[serde(default)]
-    done: bool,
-    percentage: Option<f64>,
-    time_elapsed: Option<f64>,
-    time_remaining: Option<f64>,
+    pub done: bool,
+    pub percentage: Option<f64>,
+    pub time_elapsed: Option<f64>,
+    pub time_remaining: Option<f64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct WorkerEvent {
-    state: WorkerState,
-    task_status: Option<TaskStatus>,
+    pub state: WorkerState,
+    pub task_status: Option<TaskStatus>,
     #[serde(default)]
-    errors: Vec<String>,
+    pub errors: Vec<String>,
     #[serde(default)]
-    warnings: Vec<String>,
+    pub warnings: Vec<String>,
 }
 impl WorkerEvent {
     pub(crate) fn complete(&self) -> bool {
@@ -66,9 +67,87 @@ impl WorkerEvent {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct TaskStatus {
-    task_id: TaskId,
-    task_complete: bool,
-    task_failed: bool,
+    pub task_id: TaskId,
+    pub task_complete: bool,
+    pub task_failed: bool,
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Message::Progress(event) => event.fmt(f),
+            Message::Worker(event) => event.fmt(f),
+            Message::Data { event, .. } => event.fmt(f),
+        }
+    }
+}
+
+/// eg `progress: x -0.800/1.000 mm 10%, y done`
+impl Display for ProgressEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut statuses = self.statuses.values().collect::<Vec<_>>();
+        statuses.sort_by_key(|status| &status.display_name);
+        f.write_str("progress:")?;
+        let mut sep = " ";
+        for status in statuses {
+            write!(f, "{sep}{status}")?;
+            sep = ", ";
+        }
+        Ok(())
+    }
+}
+
+/// eg `x -0.800/1.000 mm 10%` or `x done`
+impl Display for StatusView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.display_name)?;
+        if self.done {
+            return f.write_str(" done");
+        }
+        let value = |value: f64| format_number(value, self.precision);
+        if let Some(current) = self.current {
+            write!(f, " {}", value(current))?;
+            if let Some(target) = self.target {
+                write!(f, "/{}", value(target))?;
+            }
+            if let Some(unit) = &self.unit {
+                write!(f, " {unit}")?;
+            }
+        }
+        if let Some(fraction) = self.percentage {
+            write!(f, " {:.0}%", fraction * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// eg `worker RUNNING: task <id> failed, errors: ...; warnings: ...`
+impl Display for WorkerEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "worker {}", self.state)?;
+        if let Some(status) = &self.task_status {
+            write!(f, ": {status}")?;
+        }
+        if !self.errors.is_empty() {
+            write!(f, ", errors: {}", self.errors.join("; "))?;
+        }
+        if !self.warnings.is_empty() {
+            write!(f, ", warnings: {}", self.warnings.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// eg `task <id> complete`
+impl Display for TaskStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "task {}", self.task_id)?;
+        match (self.task_complete, self.task_failed) {
+            (_, true) => f.write_str(" failed"),
+            (true, false) => f.write_str(" complete"),
+            (false, false) => Ok(()),
+        }
+    }
 }
@@ -1,12 +1,13 @@
 #![allow(unused)]
 use std::collections::HashMap;
+use std::fmt::Display;
 
 use serde::Deserialize;
 use serde_json::Value;
 use url::Url;
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "name", content = "doc")]
 pub enum EventDocument {
     Stop(Stop),
@@ -21,7 +22,7 @@ pub enum EventDocument {
     StreamDatum(StreamDatum),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Stop {
     pub data_type: Option<Value>,
     pub exit_status: ExitStatus,
@@ -33,13 +34,16 @@ pub struct Stop {
     pub uid: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Start {
     #[serde(default)]
     pub data_groups: Vec<String>,
     pub data_session: Option<String>,
     pub group: Option<String>,
+    #[serde(default)]
+    pub motors: Vec<String>,
     pub owner: Option<String>,
+    pub plan_name: Option<String>,
     pub project: Option<String>,
     pub sample: Option<SampleInfo>,
     pub scan_id: Option<u32>,
@@ -47,7 +51,7 @@ pub struct Start {
     pub uid: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Descriptor {
     #[serde(default)]
     pub configuration: HashMap<String, Configuration>,
@@ -60,7 +64,7 @@ pub struct Descriptor {
     pub uid: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Event {
     pub uid: Uuid,
     pub time: f64,
@@ -70,14 +74,14 @@ pub struct Event {
     pub descriptor: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Datum {
     pub datum_id: String,
     pub datum_kwargs: HashMap<String, Value>,
     pub resource: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Resource {
     pub resource_kwargs: HashMap<String, Value>,
     pub resource_path: String,
@@ -88,24 +92,51 @@ pub struct Resource {
     pub run_start: Option<Uuid>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct EventPage {
     pub data: HashMap<String, Vec<Value>>,
     pub time: Vec<f64>,
     pub timestamps: HashMap<String, Vec<Value>>,
-    pub descriptor: String,
-    pub seq_num: Vec<i32>,
+    pub descriptor: Uuid,
+    pub seq_num: Vec<u32>,
     pub uid: Vec<Uuid>,
 }
 
-#[derive(Debug, Deserialize)]
+impl EventPage {
+    /// Split this page into the individual events it contains
+    pub fn events(&self) -> impl Iterator<Item = Event> + '_ {
+        let column = |columns: &HashMap<String, Vec<Value>>, i: usize| {
+            Value::Object(
+                columns
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.clone(), v.get(i)?.clone())))
+                    .collect(),
+            )
+        };
+        self.uid
+            .iter()
+            .zip(&self.time)
+            .zip(&self.seq_num)
+            .enumerate()
+            .map(move |(i, ((uid, time), seq_num))| Event {
+                uid: *uid,
+                time: *time,
+                data: column(&self.data, i),
+                timestamps: column(&self.timestamps, i),
+                seq_num: *seq_num,
+                descriptor: self.descriptor,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct DatumPage {
     pub datum_id: Vec<String>,
     pub datum_kwargs: HashMap<String, Vec<Value>>,
     pub resource: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct StreamResource {
     pub data_key: String,
     pub mimetype: String,
@@ -115,7 +146,7 @@ pub struct StreamResource {
     pub uri: Url,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct StreamDatum {
     pub descriptor: Uuid,
     pub indices: StreamRange,
@@ -124,7 +155,7 @@ pub struct StreamDatum {
     pub uid: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct StreamRange {
     start: i32,
     stop: i32,
@@ -137,7 +168,7 @@ pub enum PathSemantics {
     Windows,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Configuration {
     #[serde(default)]
     data: HashMap<String, Value>,
@@ -147,24 +178,24 @@ pub struct Configuration {
     timestamps: HashMap<String, Value>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DataKey {
     #[serde(default)]
-    choices: Vec<String>,
+    pub choices: Vec<String>,
     #[serde(default)]
-    dims: Vec<String>,
-    dtype: DataType,
-    dtype_numpy: Option<Value>,
-    external: Option<String>,
-    limits: Option<Limits>,
-    object_name: Option<String>,
-    precision: Option<i32>,
-    shape: Vec<Option<i32>>,
-    source: String,
-    units: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
+    pub dims: Vec<String>,
+    pub dtype: DataType,
+    pub dtype_numpy: Option<Value>,
+    pub external: Option<String>,
+    pub limits: Option<Limits>,
+    pub object_name: Option<String>,
+    pub precision: Option<i32>,
+    pub shape: Vec<Option<i32>>,
+    pub source: String,
+    pub units: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Limits {
     alarm: Option<LimitsRange>,
     control: Option<LimitsRange>,
@@ -174,19 +205,19 @@ pub struct Limits {
     warning: Option<LimitsRange>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct RdsRange {
     time_difference: f64,
     value_difference: f64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct LimitsRange {
     high: Option<f64>,
     low: Option<f64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum DataType {
     String,
@@ -196,17 +227,408 @@ pub enum DataType {
     Integer,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
 pub enum SampleInfo {
     Info(HashMap<String, Value>),
     Link(Uuid),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ExitStatus {
     Success,
     Abort,
     Fail,
 }
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ExitStatus::Success => "success",
+            ExitStatus::Abort => "abort",
+            ExitStatus::Fail => "fail",
+        })
+    }
+}
+
+impl Display for EventDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventDocument::Stop(doc) => doc.fmt(f),
+            EventDocument::Start(doc) => doc.fmt(f),
+            EventDocument::Descriptor(doc) => doc.fmt(f),
+            EventDocument::Event(doc) => doc.fmt(f),
+            EventDocument::Datum(doc) => doc.fmt(f),
+            EventDocument::Resource(doc) => doc.fmt(f),
+            EventDocument::EventPage(doc) => doc.fmt(f),
+            EventDocument::DatumPage(doc) => doc.fmt(f),
+            EventDocument::StreamResource(doc) => doc.fmt(f),
+            EventDocument::StreamDatum(doc) => doc.fmt(f),
+        }
+    }
+}
+
+/// eg `stop: success, baseline 1 event, primary 11 events (run <uid>)`
+impl Display for Stop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stop: {}", self.exit_status)?;
+        if let Some(reason) = self.reason.as_deref().filter(|r| !r.is_empty()) {
+            write!(f, " ({reason})")?;
+        }
+        for (stream, count) in sorted(&self.num_events) {
+            let plural = if *count == 1 { "" } else { "s" };
+            write!(f, ", {stream} {count} event{plural}")?;
+        }
+        write!(f, " (run {})", self.run_start)
+    }
+}
+
+/// eg `start: scan 7, plan scan, session cm12345-1, motors x (run <uid>)`
+impl Display for Start {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("start:")?;
+        let mut sep = " ";
+        if let Some(scan_id) = self.scan_id {
+            write!(f, "{sep}scan {scan_id}")?;
+            sep = ", ";
+        }
+        if let Some(plan) = &self.plan_name {
+            write!(f, "{sep}plan {plan}")?;
+            sep = ", ";
+        }
+        if let Some(session) = &self.data_session {
+            write!(f, "{sep}session {session}")?;
+            sep = ", ";
+        }
+        if let Some(sample) = &self.sample {
+            write!(f, "{sep}sample {sample}")?;
+            sep = ", ";
+        }
+        if !self.motors.is_empty() {
+            write!(f, "{sep}motors {}", self.motors.join(", "))?;
+        }
+        write!(f, " (run {})", self.uid)
+    }
+}
+
+/// eg `descriptor primary: det (integer), x (number, mm) (run <uid>)`
+impl Display for Descriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("descriptor")?;
+        if let Some(name) = &self.name {
+            write!(f, " {name}")?;
+        }
+        f.write_str(":")?;
+        let mut sep = " ";
+        for (key, data_key) in sorted(&self.data_keys) {
+            write!(f, "{sep}{key} ({data_key})")?;
+            sep = ", ";
+        }
+        write!(f, " (run {})", self.run_start)
+    }
+}
+
+impl Event {
+    /// Show this event using the precision and units of the data keys in its
+    /// descriptor, if it's known
+    pub fn described<'a>(&'a self, descriptor: Option<&'a Descriptor>) -> DescribedEvent<'a> {
+        DescribedEvent {
+            event: self,
+            descriptor,
+        }
+    }
+}
+
+/// An [`Event`] along with the descriptor that describes its data
+pub struct DescribedEvent<'a> {
+    event: &'a Event,
+    descriptor: Option<&'a Descriptor>,
+}
+
+/// eg `event 3: det=15, x=-0.6`
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.described(None).fmt(f)
+    }
+}
+
+/// eg `event 3: det=15, x=-0.600 mm`
+impl Display for DescribedEvent<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "event {}:", self.event.seq_num)?;
+        let Value::Object(data) = &self.event.data else {
+            return write!(f, " {}", self.event.data);
+        };
+        let mut sep = " ";
+        for (key, value) in data {
+            match self.descriptor.and_then(|desc| desc.data_keys.get(key)) {
+                Some(data_key) => {
+                    write!(f, "{sep}{key}={}", data_key.format(value))?;
+                    if let Some(units) = data_key.units() {
+                        write!(f, " {units}")?;
+                    }
+                }
+                None => write!(f, "{sep}{key}={value}")?,
+            }
+            sep = ", ";
+        }
+        Ok(())
+    }
+}
+
+/// eg `datum <id>: frame=3 (resource <uid>)`
+impl Display for Datum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "datum {}", self.datum_id)?;
+        let mut sep = ": ";
+        for (key, value) in sorted(&self.datum_kwargs) {
+            write!(f, "{sep}{key}={value}")?;
+            sep = ", ";
+        }
+        write!(f, " (resource {})", self.resource)
+    }
+}
+
+/// eg `resource AD_HDF5: /data/2024/cm12345-1/det.h5 (posix)`
+impl Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = std::path::Path::new(&self.root).join(&self.resource_path);
+        write!(f, "resource {}: {}", self.spec, path.display())?;
+        if let Some(semantics) = self.path_semantics {
+            write!(f, " ({semantics})")?;
+        }
+        Ok(())
+    }
+}
+
+/// eg `event page 1-11: 11 events of det, x`
+impl Display for EventPage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("event page")?;
+        if let (Some(first), Some(last)) = (self.seq_num.first(), self.seq_num.last()) {
+            write!(f, " {first}-{last}")?;
+        }
+        let plural = if self.uid.len() == 1 { "" } else { "s" };
+        write!(f, ": {} event{plural}", self.uid.len())?;
+        let mut sep = " of ";
+        for (key, _) in sorted(&self.data) {
+            write!(f, "{sep}{key}")?;
+            sep = ", ";
+        }
+        Ok(())
+    }
+}
+
+/// eg `datum page: 10 datums (resource <uid>)`
+impl Display for DatumPage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.datum_id.len() == 1 { "" } else { "s" };
+        write!(
+            f,
+            "datum page: {} datum{plural} (resource {})",
+            self.datum_id.len(),
+            self.resource
+        )
+    }
+}
+
+/// eg `stream resource det: file:///data/det.h5 (application/x-hdf5)`
+impl Display for StreamResource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "stream resource {}: {} ({})",
+            self.data_key, self.uri, self.mimetype
+        )
+    }
+}
+
+/// eg `stream datum: events 1..11, indices 0..10 (stream resource <uid>)`
+impl Display for StreamDatum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "stream datum: events {}, indices {} (stream resource {})",
+            self.seq_nums, self.indices, self.stream_resource
+        )
+    }
+}
+
+/// The half-open range `start..stop`
+impl Display for StreamRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.stop)
+    }
+}
+
+impl Display for PathSemantics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PathSemantics::Posix => "posix",
+            PathSemantics::Windows => "windows",
+        })
+    }
+}
+
+/// eg `number`, `number, mm` or `array [1024, 768]`
+impl Display for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.dtype.fmt(f)?;
+        if !self.shape.is_empty() {
+            let dims = self
+                .shape
+                .iter()
+                .map(|dim| dim.map_or("?".into(), |d| d.to_string()))
+                .collect::<Vec<_>>();
+            write!(f, " [{}]", dims.join(", "))?;
+        }
+        if let Some(units) = self.units() {
+            write!(f, ", {units}")?;
+        }
+        Ok(())
+    }
+}
+
+impl DataKey {
+    /// The units of the values, if they have any
+    pub fn units(&self) -> Option<&str> {
+        self.units.as_deref().filter(|u| !u.is_empty())
+    }
+
+    /// A value of this key, rounded to its precision if it has one
+    pub fn format(&self, value: &Value) -> String {
+        match value {
+            Value::Number(n) if !n.is_i64() && !n.is_u64() && self.precision.is_some() => {
+                format_number(n.as_f64().unwrap_or(f64::NAN), self.precision)
+            }
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+/// A number rounded to a precision (number of decimal places), if given
+pub fn format_number(value: f64, precision: Option<i32>) -> String {
+    match precision {
+        Some(prec) => format!("{value:.prec$}", prec = prec.max(0) as usize),
+        None => format!("{value}"),
+    }
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DataType::String => "string",
+            DataType::Number => "number",
+            DataType::Array => "array",
+            DataType::Boolean => "boolean",
+            DataType::Integer => "integer",
+        })
+    }
+}
+
+/// eg `{name="silicon", temperature=300}` or the UID of a linked sample
+impl Display for SampleInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SampleInfo::Info(info) => {
+                let mut sep = "";
+                f.write_str("{")?;
+                for (key, value) in sorted(info) {
+                    write!(f, "{sep}{key}={value}")?;
+                    sep = ", ";
+                }
+                f.write_str("}")
+            }
+            SampleInfo::Link(uid) => uid.fmt(f),
+        }
+    }
+}
+
+/// The entries of a map ordered by key so that they are shown consistently
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Descriptor, Event, Resource, Start, Stop};
+
+    #[test]
+    fn event_uses_precision_and_units() {
+        let descriptor: Descriptor = serde_json::from_value(json!({
+            "uid": "bbbbbbbb-1111-4222-8333-444455556666",
+            "run_start": "aaaaaaaa-1111-4222-8333-444455556666",
+            "time": 0.0,
+            "data_keys": {
+                "x": {"dtype": "number", "shape": [], "source": "PV:X", "precision": 3, "units": "mm"},
+                "det": {"dtype": "integer", "shape": [], "source": "PV:DET", "precision": 2},
+            },
+        }))
+        .expect("valid descriptor");
+        let event: Event = serde_json::from_value(json!({
+            "uid": "dddddddd-0000-4000-8000-000000000000",
+            "time": 0.0,
+            "descriptor": "bbbbbbbb-1111-4222-8333-444455556666",
+            "seq_num": 2,
+            "data": {"det": 4, "x": -0.8, "other": 0.123456},
+            "timestamps": {},
+        }))
+        .expect("valid event");
+        assert_eq!(
+            event.described(Some(&descriptor)).to_string(),
+            "event 2: det=4, other=0.123456, x=-0.800 mm"
+        );
+        assert_eq!(event.to_string(), "event 2: det=4, other=0.123456, x=-0.8");
+    }
+
+    #[test]
+    fn start_and_resource_details() {
+        let start: Start = serde_json::from_value(json!({
+            "uid": "aaaaaaaa-1111-4222-8333-444455556666",
+            "time": 0.0,
+            "scan_id": 7,
+            "sample": {"name": "silicon", "temperature": 300},
+        }))
+        .expect("valid start");
+        assert_eq!(
+            start.to_string(),
+            "start: scan 7, sample {name=\"silicon\", temperature=300} (run aaaaaaaa-1111-4222-8333-444455556666)"
+        );
+        let resource: Resource = serde_json::from_value(json!({
+            "uid": "eeeeeeee-1111-4222-8333-444455556666",
+            "spec": "AD_HDF5",
+            "root": "/data",
+            "resource_path": "det.h5",
+            "resource_kwargs": {},
+            "path_semantics": "posix",
+        }))
+        .expect("valid resource");
+        assert_eq!(
+            resource.to_string(),
+            "resource AD_HDF5: /data/det.h5 (posix)"
+        );
+    }
+
+    #[test]
+    fn stop_lists_streams_in_order() {
+        let stop: Stop = serde_json::from_value(json!({
+            "uid": "ffffffff-1111-4222-8333-444455556666",
+            "run_start": "aaaaaaaa-1111-4222-8333-444455556666",
+            "time": 0.0,
+            "exit_status": "success",
+            "num_events": {"primary": 11, "baseline": 1},
+        }))
+        .expect("valid stop");
+        assert_eq!(
+            stop.to_string(),
+            "stop: success, baseline 1 event, primary 11 events \
+             (run aaaaaaaa-1111-4222-8333-444455556666)"
+        );
+    }
+}
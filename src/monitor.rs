@@ -0,0 +1,167 @@
+use std::fmt::Display;
+
+use crate::callbacks::{Callback, FileExport, LiveTable, PeakStats, SequenceCheck};
+use crate::messages::data_model::{EventDocument, ExitStatus, Stop};
+use crate::messages::{Message, WorkerEvent};
+use crate::progress::{Printer, ProgressBars};
+
+/// Renders the messages from a running plan to the terminal
+///
+/// Progress updates are shown as bars, documents are passed to the live table
+/// and peak statistics callbacks (and saved if requested) and worker events
+/// are printed as they arrive, on one line each unless `verbose`. Warnings are
+/// printed if any documents appear to have been lost.
+pub struct Monitor {
+    printer: Printer,
+    progress: ProgressBars,
+    table: LiveTable,
+    peaks: PeakStats,
+    sequence: SequenceCheck,
+    export: Option<FileExport>,
+    outcome: Outcome,
+    verbose: bool,
+}
+
+/// What happened to the task being monitored, collected from worker events
+/// and stop documents
+///
+/// Displayed as a summary line followed by a line for each error and warning.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// The worst exit status of any run in the task
+    exit_status: Option<ExitStatus>,
+    reasons: Vec<String>,
+    task_failed: bool,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Monitor {
+    pub fn new(printer: Printer, export: Option<FileExport>, verbose: bool) -> Self {
+        Self {
+            progress: ProgressBars::new(&printer),
+            table: LiveTable::new(printer.clone()),
+            peaks: PeakStats::new(printer.clone()),
+            sequence: SequenceCheck::new(printer.clone()),
+            printer,
+            export,
+            outcome: Outcome::default(),
+            verbose,
+        }
+    }
+
+    pub fn handle(&mut self, msg: &Message) {
+        match msg {
+            Message::Progress(event) => self.progress.update(event),
+            Message::Worker(worker_event) => {
+                match self.verbose {
+                    true => self.printer.println(format_args!("{worker_event:#?}")),
+                    false => self.printer.println(worker_event),
+                }
+                self.outcome.worker_event(worker_event);
+            }
+            Message::Data { event, .. } => {
+                if self.sequence.is_duplicate(event) {
+                    return;
+                }
+                if let EventDocument::Stop(stop) = event {
+                    self.outcome.stop(stop);
+                }
+                self.sequence.document(event);
+                self.table.document(event);
+                self.peaks.document(event);
+                if let Some(export) = &mut self.export {
+                    export.document(event);
+                }
+            }
+        }
+    }
+
+    /// Monitor a task that may already be partway through a run, so documents
+    /// sent before monitoring started are not reported as missed
+    pub fn attached(mut self) -> Self {
+        self.sequence.attach();
+        self
+    }
+
+    /// Clear any remaining progress bars, close the files of any run that
+    /// didn't stop and print the outcome of the task
+    pub fn finish(&mut self) {
+        self.progress.clear();
+        if let Some(export) = &mut self.export {
+            export.finish();
+        }
+        self.printer.println(&self.outcome);
+    }
+
+    /// The printer used for output, so other output can be printed above the
+    /// progress bars
+    pub fn printer(&self) -> &Printer {
+        &self.printer
+    }
+
+    pub fn outcome(&self) -> &Outcome {
+        &self.outcome
+    }
+}
+
+impl Outcome {
+    fn worker_event(&mut self, event: &WorkerEvent) {
+        if let Some(status) = &event.task_status {
+            self.task_failed |= status.task_failed;
+        }
+        extend_unique(&mut self.errors, &event.errors);
+        extend_unique(&mut self.warnings, &event.warnings);
+    }
+
+    fn stop(&mut self, doc: &Stop) {
+        self.exit_status = self.exit_status.max(Some(doc.exit_status));
+        if let Some(reason) = doc.reason.as_deref().filter(|r| !r.is_empty()) {
+            extend_unique(&mut self.reasons, &[reason.to_owned()]);
+        }
+    }
+
+    /// Whether the task failed, was aborted or reported any errors
+    pub fn failed(&self) -> bool {
+        self.task_failed
+            || !self.errors.is_empty()
+            || self.exit_status.is_some_and(|st| st != ExitStatus::Success)
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match (self.exit_status, self.failed()) {
+            (Some(status), _) => status.to_string(),
+            (None, true) => "failed".into(),
+            (None, false) => "complete".into(),
+        };
+        write!(f, "Task finished: {status}")?;
+        if !self.reasons.is_empty() {
+            write!(f, " ({})", self.reasons.join("; "))?;
+        }
+        write!(
+            f,
+            ", {} error{}, {} warning{}",
+            self.errors.len(),
+            if self.errors.len() == 1 { "" } else { "s" },
+            self.warnings.len(),
+            if self.warnings.len() == 1 { "" } else { "s" },
+        )?;
+        for error in &self.errors {
+            write!(f, "\n  Error: {error}")?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\n  Warning: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+fn extend_unique(existing: &mut Vec<String>, new: &[String]) {
+    for item in new {
+        if !existing.contains(item) {
+            existing.push(item.clone());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// How the results of commands should be written to stdout
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns for reading in a terminal
+    #[default]
+    Table,
+    /// JSON, one document per command
+    Json,
+    /// YAML, one document per command
+    Yaml,
+}
+
+impl OutputFormat {
+    /// Write a value to stdout in this format
+    pub fn print<T: Serialize + Tabular>(&self, value: &T) {
+        match self {
+            OutputFormat::Table => print!("{}", value.table()),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(value).expect("Entities are valid JSON")
+            ),
+            OutputFormat::Yaml => print!(
+                "{}",
+                serde_yaml::to_string(value).expect("Entities are valid YAML")
+            ),
+        }
+    }
+
+    /// Write one of a stream of values to stdout, eg for commands that report
+    /// changes as they happen
+    ///
+    /// Tables are replaced by the value's single line form and JSON is written
+    /// one value per line.
+    pub fn print_line<T: Serialize + Display>(&self, value: &T) {
+        match self {
+            OutputFormat::Table => println!("{value}"),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string(value).expect("Entities are valid JSON")
+            ),
+            OutputFormat::Yaml => print!(
+                "---\n{}",
+                serde_yaml::to_string(value).expect("Entities are valid YAML")
+            ),
+        }
+    }
+}
+
+/// Something that can be shown as a table of rows
+pub trait Tabular {
+    fn table(&self) -> Table;
+}
+
+/// Something that can be shown as a single row of a table
+pub trait Row {
+    const HEADERS: &'static [&'static str];
+    fn cells(&self) -> Vec<String>;
+}
+
+impl<T: Row> Tabular for Vec<T> {
+    fn table(&self) -> Table {
+        Table::new(T::HEADERS).rows(self.iter().map(Row::cells))
+    }
+}
+
+/// Rows of text with a header, printed with each column aligned
+#[derive(Debug, Default)]
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+    notes: Vec<String>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self {
+            headers: headers.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn row<I: IntoIterator<Item = S>, S: ToString>(mut self, cells: I) -> Self {
+        self.rows
+            .push(cells.into_iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    pub fn rows<R: IntoIterator<Item = I>, I: IntoIterator<Item = S>, S: ToString>(
+        self,
+        rows: R,
+    ) -> Self {
+        rows.into_iter().fold(self, Table::row)
+    }
+
+    /// Add a line of text to be printed after the table
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut widths = self.headers.iter().map(|h| h.len()).collect::<Vec<_>>();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate() {
+                let len = cell.chars().count();
+                match widths.get_mut(i) {
+                    Some(w) => *w = (*w).max(len),
+                    None => widths.push(len),
+                }
+            }
+        }
+        let headers = self.headers.iter().map(|h| h.to_string()).collect();
+        for row in std::iter::once(&headers).chain(&self.rows) {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            // Padding the last column would leave trailing whitespace
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        for note in &self.notes {
+            writeln!(f, "{note}")?;
+        }
+        Ok(())
+    }
+}
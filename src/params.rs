@@ -0,0 +1,377 @@
+use std::io::Read;
+use std::path::Path;
+
+use jsonschema::paths::LocationSegment;
+use serde_json::{Map, Value};
+
+use crate::error::{BcliError, Result};
+
+mod flags;
+mod literal;
+
+pub use flags::PlanFlags;
+
+/// The types that JSON schema knows about - anything else is a python type
+/// added to the schema by blueapi (eg `bluesky.protocols.Readable`)
+const JSON_TYPES: &[&str] = &[
+    "array", "boolean", "integer", "null", "number", "object", "string",
+];
+
+/// Read parameters from the command line
+///
+/// The source is either inline JSON (or a python literal), a file (`@params.json` or `@params.yaml`)
+/// or `-` to read JSON or YAML from stdin. In each case the parameters must be
+/// an object mapping parameter names to values.
+pub fn load(source: &str) -> Result<Map<String, Value>> {
+    let (origin, parsed) = match source {
+        "-" => {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|e| BcliError::Io("<stdin>".into(), e))?;
+            ("stdin".into(), parse_json_or_yaml(&input))
+        }
+        _ => match source.strip_prefix('@') {
+            Some(path) => {
+                let path = Path::new(path);
+                let content =
+                    std::fs::read_to_string(path).map_err(|e| BcliError::Io(path.to_owned(), e))?;
+                let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("yaml" | "yml") => {
+                        serde_yaml::from_str(&content).map_err(|e| e.to_string())
+                    }
+                    _ => serde_json::from_str(&content).map_err(|e| e.to_string()),
+                };
+                (path.display().to_string(), parsed)
+            }
+            None => (
+                "inline parameters".into(),
+                literal::parse(source).map_err(|e| e.to_string()),
+            ),
+        },
+    };
+    match parsed {
+        Ok(Value::Object(params)) => Ok(params),
+        Ok(_) => Err(BcliError::InvalidParameters(format!(
+            "{origin} must be an object mapping parameter names to values"
+        ))),
+        Err(e) => Err(BcliError::InvalidParameters(format!(
+            "couldn't parse {origin}: {e}"
+        ))),
+    }
+}
+
+/// Read parameters from several sources, merged in the order they were given
+///
+/// Later sources replace any top level parameters given by earlier ones.
+pub fn load_all(sources: &[String]) -> Result<Map<String, Value>> {
+    let mut params = Map::new();
+    for source in sources {
+        params.extend(load(source)?);
+    }
+    Ok(params)
+}
+
+/// Parse input that could be either JSON or YAML, preferring JSON errors if it
+/// looks like JSON
+fn parse_json_or_yaml(input: &str) -> Result<Value, String> {
+    match input.trim_start().starts_with('{') {
+        true => serde_json::from_str(input).map_err(|e| e.to_string()),
+        false => serde_yaml::from_str(input).map_err(|e| e.to_string()),
+    }
+}
+
+/// Check plan parameters against the JSON schema of the plan
+///
+/// All problems are reported together, one per line, with the location of the
+/// parameter that caused it.
+pub fn validate(plan: &str, schema: &Value, params: &Value) -> Result<()> {
+    let schema = sanitise(schema.clone());
+    let validator = match jsonschema::validator_for(&schema) {
+        Ok(validator) => validator,
+        Err(e) => {
+            // The server will still validate the parameters so this isn't fatal
+            eprintln!("Warning: couldn't read parameter schema for {plan}, not validating: {e}");
+            return Ok(());
+        }
+    };
+    let problems = validator
+        .iter_errors(params)
+        .map(|err| format!("\n  {}: {err}", json_path(err.instance_path().into_iter())))
+        .collect::<String>();
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(BcliError::InvalidParameters(format!(
+            "parameters do not match {plan}{problems}"
+        ))),
+    }
+}
+
+/// Remove anything from the schema that would stop it being a valid JSON schema
+///
+/// Device parameters are given python types by blueapi which are not valid
+/// JSON schema types. Devices are passed by name so they are replaced by
+/// strings.
+fn sanitise(schema: Value) -> Value {
+    match schema {
+        Value::Object(obj) => Value::Object(
+            obj.into_iter()
+                .map(|(key, value)| match (key.as_str(), value) {
+                    ("type", Value::String(ty)) if !JSON_TYPES.contains(&ty.as_str()) => {
+                        (key, Value::String("string".into()))
+                    }
+                    (_, value) => (key, sanitise(value)),
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(sanitise).collect()),
+        other => other,
+    }
+}
+
+/// Format a location within the parameters, eg `$.detectors[0]`
+fn json_path<'a>(segments: impl Iterator<Item = LocationSegment<'a>>) -> String {
+    let mut path = String::from("$");
+    for segment in segments {
+        match segment {
+            LocationSegment::Property(name) => {
+                path.push('.');
+                path.push_str(&name);
+            }
+            LocationSegment::Index(idx) => path.push_str(&format!("[{idx}]")),
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+    use serde_json::{Value, json};
+    use uuid::Uuid;
+
+    use super::{PlanFlags, load, load_all, parse_json_or_yaml, validate};
+    use crate::cli::{Cli, CliArgs, RunArgs};
+
+    /// A directory that is removed when the test finishes
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("bcli-params-{}", Uuid::new_v4()));
+            std::fs::create_dir(&dir).expect("creating scratch directory");
+            Self(dir)
+        }
+
+        /// Write a file and return the `@path` source for it
+        fn file(&self, name: &str, content: &str) -> String {
+            let path = self.0.join(name);
+            std::fs::write(&path, content).expect("writing parameters file");
+            format!("@{}", path.display())
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn loaded(source: &str) -> Value {
+        Value::Object(load(source).expect("loading parameters"))
+    }
+
+    fn load_error(source: &str) -> String {
+        match load(source) {
+            Err(e) => e.to_string(),
+            Ok(params) => panic!("{source} loaded as {params:?}"),
+        }
+    }
+
+    fn run_args(args: &[&str]) -> RunArgs {
+        let args = ["bcli", "run", "count", "-i", "cm12345-1"]
+            .iter()
+            .chain(args);
+        match Cli::try_parse_from(args).expect("valid arguments").command {
+            CliArgs::Run(run) => run,
+            other => panic!("parsed as {other:?}"),
+        }
+    }
+
+    fn schema() -> Value {
+        json!({
+            "properties": {
+                "detectors": {
+                    "type": "array",
+                    "items": {"type": "bluesky.protocols.Readable"}
+                },
+                "num": {"type": "integer", "minimum": 1},
+                "delay": {"type": "number"}
+            },
+            "required": ["detectors"]
+        })
+    }
+
+    #[test]
+    fn inline() {
+        assert_eq!(loaded(r#"{"num": 5}"#), json!({"num": 5}));
+        assert_eq!(
+            loaded("{'detectors': ('det',), 'relative': True}"),
+            json!({"detectors": ["det"], "relative": true})
+        );
+    }
+
+    #[test]
+    fn files() {
+        let dir = Scratch::new();
+        let expected = json!({"detectors": ["det"], "num": 5});
+        assert_eq!(
+            loaded(&dir.file("p.json", r#"{"detectors": ["det"], "num": 5}"#)),
+            expected
+        );
+        assert_eq!(
+            loaded(&dir.file("p.yaml", "detectors: [det]\nnum: 5\n")),
+            expected
+        );
+        assert_eq!(
+            loaded(&dir.file("p.yml", "detectors:\n  - det\nnum: 5\n")),
+            expected
+        );
+        // Anything that isn't YAML is read as JSON
+        assert!(
+            load_error(&dir.file("p.txt", "num: 5")).starts_with(&format!(
+                "Invalid plan parameters: couldn't parse {}",
+                dir.0.join("p.txt").display()
+            ))
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        assert_eq!(
+            load_error("[1, 2]"),
+            "Invalid plan parameters: inline parameters must be an object mapping parameter names to values"
+        );
+        assert!(
+            load_error("{'num': }")
+                .starts_with("Invalid plan parameters: couldn't parse inline parameters: ")
+        );
+        let dir = Scratch::new();
+        assert_eq!(
+            load_error(&dir.file("p.yaml", "- 1\n- 2\n")),
+            format!(
+                "Invalid plan parameters: {} must be an object mapping parameter names to values",
+                dir.0.join("p.yaml").display()
+            )
+        );
+        let missing = dir.0.join("missing.json");
+        assert!(
+            load_error(&format!("@{}", missing.display())).contains(&missing.display().to_string())
+        );
+    }
+
+    #[test]
+    fn stdin_formats() {
+        assert_eq!(parse_json_or_yaml("{\"num\": 5}"), Ok(json!({"num": 5})));
+        assert_eq!(parse_json_or_yaml("num: 5\n"), Ok(json!({"num": 5})));
+        // Input that looks like JSON gets JSON's error rather than YAML's
+        assert_eq!(
+            parse_json_or_yaml("{\"num\": }"),
+            Err("expected value at line 1 column 9".into())
+        );
+    }
+
+    #[test]
+    fn later_sources_override_earlier() {
+        let dir = Scratch::new();
+        let base = dir.file(
+            "base.yaml",
+            "detectors: [det]\nnum: 5\nmetadata: {sample: a, user: b}\n",
+        );
+        let sources = [
+            base.clone(),
+            r#"{"num": 10, "metadata": {"sample": "c"}}"#.into(),
+        ];
+        // Only top level parameters are merged
+        assert_eq!(
+            Value::Object(load_all(&sources).expect("loading parameters")),
+            json!({"detectors": ["det"], "num": 10, "metadata": {"sample": "c"}})
+        );
+        let sources = [sources[1].clone(), base];
+        assert_eq!(
+            Value::Object(load_all(&sources).expect("loading parameters")),
+            json!({"detectors": ["det"], "num": 5, "metadata": {"sample": "a", "user": "b"}})
+        );
+    }
+
+    #[test]
+    fn flags_override_sources() {
+        let args = run_args(&["{'num': 5, 'delay': 1}", "--num", "3"]);
+        let mut params = args.parameters().expect("loading parameters");
+        params.extend(
+            PlanFlags::new(&schema())
+                .parse("count", args.flags())
+                .expect("parsing flags"),
+        );
+        assert_eq!(Value::Object(params), json!({"num": 3, "delay": 1}));
+    }
+
+    #[test]
+    fn no_validate() {
+        assert!(!run_args(&["{}"]).no_validate);
+        let args = run_args(&["--no-validate", "{}", "--num", "3"]);
+        assert!(args.no_validate);
+        assert_eq!(args.flags(), ["--num", "3"]);
+        // Options for run can't come after the parameters
+        let args = run_args(&["{}", "--num", "3", "--no-validate"]);
+        assert!(!args.no_validate);
+        assert_eq!(args.flags(), ["--num", "3", "--no-validate"]);
+    }
+
+    #[test]
+    fn valid_parameters() {
+        // Device types from blueapi are checked as strings
+        validate("count", &schema(), &json!({"detectors": ["det"], "num": 1}))
+            .expect("valid parameters");
+        assert!(validate("count", &schema(), &json!({"detectors": [{"name": "det"}]})).is_err());
+    }
+
+    #[test]
+    fn problems_are_listed_with_their_location() {
+        let error = validate(
+            "count",
+            &schema(),
+            &json!({"detectors": ["det", 3], "num": 0, "delay": "1s"}),
+        )
+        .expect_err("invalid parameters")
+        .to_string();
+        let mut lines = error.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines.remove(0),
+            "Invalid plan parameters: parameters do not match count"
+        );
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                r#"  $.delay: "1s" is not of type "number""#,
+                r#"  $.detectors[1]: 3 is not of type "string""#,
+                "  $.num: 0 is less than the minimum of 1",
+            ]
+        );
+        assert!(
+            validate("count", &schema(), &json!({}))
+                .expect_err("missing parameter")
+                .to_string()
+                .ends_with(r#"$: "detectors" is a required property"#)
+        );
+    }
+
+    #[test]
+    fn unreadable_schema_is_not_fatal() {
+        let schema = json!({"properties": {"num": {"type": 5}}});
+        validate("count", &schema, &json!({"num": "x"})).expect("not validated");
+    }
+}
@@ -0,0 +1,472 @@
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, Command};
+use serde_json::{Map, Number, Value};
+
+use super::literal;
+use crate::entities::PlanSpec;
+use crate::error::{BcliError, Result};
+
+/// How deeply nested `$ref`s are followed before giving up and accepting JSON
+const MAX_DEPTH: usize = 8;
+
+/// Command line flags for the parameters of a plan, generated from its schema
+///
+/// Each property in the schema becomes a `--long-flag` (underscores are
+/// replaced by hyphens but the original name is accepted as an alias). Values
+/// are coerced to the type given in the schema, lists of scalars can be given
+/// as several values or comma separated, and anything that can't be built from
+/// a plain string (eg objects) is given as JSON or a python literal.
+pub struct PlanFlags {
+    params: Vec<Param>,
+}
+
+/// A single property of a plan's schema
+struct Param {
+    name: String,
+    kind: Kind,
+    description: Option<String>,
+    default: Option<Value>,
+    required: bool,
+}
+
+/// The type of a plan parameter, as far as it can be given on the command line
+#[derive(Debug, Clone)]
+enum Kind {
+    Integer,
+    Number,
+    Boolean,
+    String,
+    /// A python type added by blueapi, passed by name
+    Device,
+    Null,
+    /// One of a fixed set of values
+    Enum(Vec<Value>),
+    Array(Box<Kind>),
+    /// Any one of several types, eg `float | list[float]`
+    Union(Vec<Kind>),
+    /// Anything else (objects, nested models etc), given as JSON
+    Json,
+}
+
+impl PlanFlags {
+    pub fn new(schema: &Value) -> Self {
+        let required = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let params = schema
+            .get("properties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .map(|(name, prop)| {
+                let prop = resolve(prop, schema);
+                Param {
+                    name: name.clone(),
+                    kind: Kind::from_schema(prop, schema, 0),
+                    description: ["description", "title"]
+                        .iter()
+                        .find_map(|key| prop.get(key).and_then(Value::as_str))
+                        .map(|desc| desc.lines().next().unwrap_or_default().to_owned()),
+                    default: prop.get("default").cloned(),
+                    required: required.iter().any(|req| req == name.as_str()),
+                }
+            })
+            .collect();
+        Self { params }
+    }
+
+    /// Parse flags given for the parameters of a plan into the parameters
+    /// they represent
+    pub fn parse(&self, plan: &str, args: &[String]) -> Result<Map<String, Value>> {
+        let matches = Command::new(format!("bcli run {plan}"))
+            .no_binary_name(true)
+            .disable_help_flag(true)
+            .override_usage(usage(plan))
+            .args(self.params.iter().map(Param::arg))
+            .try_get_matches_from(args)
+            .map_err(|e| {
+                let msg = e.render().to_string();
+                let msg = msg.strip_prefix("error: ").unwrap_or(&msg);
+                BcliError::InvalidParameters(msg.trim_end().to_owned())
+            })?;
+        let mut params = Map::new();
+        for param in &self.params {
+            let Some(values) = matches.get_many::<String>(&param.id()) else {
+                continue;
+            };
+            let values = values.cloned().collect::<Vec<_>>();
+            let value = param
+                .kind
+                .coerce(&values)
+                .map_err(|e| BcliError::InvalidParameters(format!("--{}: {e}", param.long())))?;
+            params.insert(param.name.clone(), value);
+        }
+        Ok(params)
+    }
+
+    /// Help for running a plan, listing its parameters after the usual options
+    /// for `run`
+    pub fn help(&self, run: Command, plan: &PlanSpec) -> String {
+        let mut cmd = run.override_usage(usage(&plan.name));
+        if let Some(description) = &plan.description {
+            cmd = cmd.about(description.clone());
+        }
+        cmd = cmd.next_help_heading("Plan parameters");
+        for param in &self.params {
+            // Parameters that clash with the options of `run` can still be
+            // given after the plan name but can't be shown alongside them
+            if !cmd
+                .get_arguments()
+                .any(|arg| arg.get_long() == Some(param.long().as_str()))
+            {
+                cmd = cmd.arg(param.arg());
+            }
+        }
+        cmd.render_help().to_string()
+    }
+}
+
+fn usage(plan: &str) -> String {
+    format!("bcli run [OPTIONS] {plan} [JSON] [--<PARAMETER> <VALUE>...]...")
+}
+
+/// Follow a local `$ref` (eg `#/$defs/Model`) if the schema is one
+fn resolve<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|path| path.strip_prefix('#'))
+        .and_then(|path| root.pointer(path))
+        .unwrap_or(schema)
+}
+
+impl Param {
+    fn id(&self) -> String {
+        format!("param:{}", self.name)
+    }
+
+    fn long(&self) -> String {
+        self.name.replace('_', "-")
+    }
+
+    fn arg(&self) -> Arg {
+        let mut arg = Arg::new(self.id())
+            .long(self.long())
+            .value_name(self.kind.value_name())
+            .help(self.help());
+        if self.long() != self.name {
+            arg = arg.alias(self.name.clone());
+        }
+        arg = match &self.kind {
+            Kind::Boolean => arg.num_args(0..=1).default_missing_value("true"),
+            kind if kind.accepts_many() => arg.num_args(1..).action(ArgAction::Append),
+            _ => arg,
+        };
+        if let Kind::Enum(values) = &self.kind {
+            arg = arg.value_parser(PossibleValuesParser::new(values.iter().map(enum_name)));
+        }
+        arg
+    }
+
+    fn help(&self) -> String {
+        let mut help = self.description.clone().unwrap_or_default();
+        if let Some(default) = &self.default {
+            let default = match default {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            help.push_str(&format!(" [default: {default}]"));
+        }
+        if self.required {
+            help.push_str(" [required]");
+        }
+        help.trim_start().to_owned()
+    }
+}
+
+impl Kind {
+    fn from_schema(schema: &Value, root: &Value, depth: usize) -> Self {
+        if depth > MAX_DEPTH {
+            return Kind::Json;
+        }
+        let schema = resolve(schema, root);
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            return Kind::Enum(values.clone());
+        }
+        if let Some(Value::String(_)) = schema.get("const") {
+            return Kind::String;
+        }
+        let options = ["anyOf", "oneOf", "allOf"]
+            .iter()
+            .find_map(|key| schema.get(key).and_then(Value::as_array));
+        if let Some(options) = options {
+            return Kind::union(
+                options
+                    .iter()
+                    .map(|opt| Kind::from_schema(opt, root, depth + 1)),
+            );
+        }
+        match schema.get("type") {
+            Some(Value::String(ty)) => Kind::from_type(ty, schema, root, depth),
+            Some(Value::Array(types)) => Kind::union(
+                types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(|ty| Kind::from_type(ty, schema, root, depth)),
+            ),
+            _ => Kind::Json,
+        }
+    }
+
+    fn from_type(ty: &str, schema: &Value, root: &Value, depth: usize) -> Self {
+        match ty {
+            "integer" => Kind::Integer,
+            "number" => Kind::Number,
+            "boolean" => Kind::Boolean,
+            "string" => Kind::String,
+            "null" => Kind::Null,
+            "object" => Kind::Json,
+            "array" => Kind::Array(Box::new(
+                schema
+                    .get("items")
+                    .map(|items| Kind::from_schema(items, root, depth + 1))
+                    .unwrap_or(Kind::Json),
+            )),
+            _ => Kind::Device,
+        }
+    }
+
+    /// Combine alternatives, ignoring `null` which can't usefully be given as
+    /// a flag (leaving the flag out has the same effect)
+    fn union(kinds: impl Iterator<Item = Kind>) -> Self {
+        let mut kinds = kinds
+            .filter(|kind| !matches!(kind, Kind::Null))
+            .collect::<Vec<_>>();
+        match kinds.len() {
+            0 => Kind::Null,
+            1 => kinds.remove(0),
+            _ => Kind::Union(kinds),
+        }
+    }
+
+    /// Whether values of this kind are given as a single plain string
+    fn is_scalar(&self) -> bool {
+        matches!(
+            self,
+            Kind::Integer
+                | Kind::Number
+                | Kind::Boolean
+                | Kind::String
+                | Kind::Device
+                | Kind::Null
+                | Kind::Enum(_)
+        )
+    }
+
+    fn accepts_many(&self) -> bool {
+        match self {
+            Kind::Array(_) => true,
+            Kind::Union(kinds) => kinds.iter().any(Kind::accepts_many),
+            _ => false,
+        }
+    }
+
+    fn value_name(&self) -> String {
+        match self {
+            Kind::Integer => "INTEGER".into(),
+            Kind::Number => "NUMBER".into(),
+            Kind::Boolean => "BOOLEAN".into(),
+            Kind::String => "STRING".into(),
+            Kind::Device => "DEVICE".into(),
+            Kind::Null => "NULL".into(),
+            Kind::Enum(_) => "VALUE".into(),
+            Kind::Array(item) => item.value_name(),
+            Kind::Json => "JSON".into(),
+            Kind::Union(kinds) => {
+                let mut names = Vec::<String>::new();
+                for name in kinds.iter().map(Kind::value_name) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                names.join("|")
+            }
+        }
+    }
+
+    /// Build a parameter from the value(s) given for it
+    fn coerce(&self, values: &[String]) -> Result<Value, String> {
+        match (self, values) {
+            (Kind::Array(item), values) => {
+                let mut items = Vec::new();
+                for value in values {
+                    if !item.is_scalar() {
+                        items.push(item.coerce_one(value)?);
+                    } else if value.starts_with(['[', '(']) {
+                        // The whole list given as JSON or a python literal
+                        match literal::parse(value) {
+                            Ok(Value::Array(values)) => items.extend(values),
+                            Ok(_) => return Err(format!("'{value}' is not a list")),
+                            Err(e) => return Err(format!("invalid value: {e}")),
+                        }
+                    } else {
+                        for part in value.split(',') {
+                            items.push(item.coerce_one(part)?);
+                        }
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            (Kind::Union(kinds), values) => {
+                // Prefer a single value where only one is given, eg `--delay 0.1`
+                // for `float | list[float]`, otherwise try each alternative in turn
+                let single = match values {
+                    [value] => kinds
+                        .iter()
+                        .filter(|kind| !kind.accepts_many())
+                        .find_map(|kind| kind.coerce_one(value).ok()),
+                    _ => None,
+                };
+                single
+                    .or_else(|| {
+                        kinds
+                            .iter()
+                            .filter(|kind| kind.accepts_many())
+                            .find_map(|kind| kind.coerce(values).ok())
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "'{}' is not a valid {}",
+                            values.join(","),
+                            self.value_name()
+                        )
+                    })
+            }
+            (kind, [value]) => kind.coerce_one(value),
+            (_, values) => Err(format!("expected a single value, got {}", values.len())),
+        }
+    }
+
+    fn coerce_one(&self, value: &str) -> Result<Value, String> {
+        match self {
+            Kind::Integer => value
+                .parse::<i64>()
+                .map(Value::from)
+                .map_err(|_| format!("'{value}' is not an integer")),
+            Kind::Number => value
+                .parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(|| format!("'{value}' is not a number")),
+            Kind::Boolean => match value.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "1" => Ok(Value::Bool(true)),
+                "false" | "no" | "0" => Ok(Value::Bool(false)),
+                _ => Err(format!("'{value}' is not a boolean (true or false)")),
+            },
+            Kind::String | Kind::Device => Ok(Value::String(value.into())),
+            Kind::Null => match value {
+                "null" | "none" | "None" => Ok(Value::Null),
+                _ => Err(format!("'{value}' is not null")),
+            },
+            Kind::Enum(values) => values
+                .iter()
+                .find(|v| enum_name(v) == value)
+                .cloned()
+                .ok_or_else(|| format!("'{value}' is not one of the allowed values")),
+            Kind::Array(_) | Kind::Union(_) | Kind::Json => {
+                literal::parse(value).map_err(|e| format!("invalid value: {e}"))
+            }
+        }
+    }
+}
+
+fn enum_name(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::PlanFlags;
+
+    fn parse(args: &[&str]) -> Result<Value, String> {
+        let schema = json!({
+            "properties": {
+                "detectors": {"type": "array", "items": {"type": "string"}},
+                "shape": {"type": "array", "items": {"type": "integer"}},
+                "positions": {
+                    "type": "array",
+                    "items": {"type": "array", "items": {"type": "number"}}
+                },
+                "delay": {
+                    "anyOf": [
+                        {"type": "number"},
+                        {"type": "array", "items": {"type": "number"}}
+                    ]
+                },
+                "metadata": {"type": "object"}
+            }
+        });
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        PlanFlags::new(&schema)
+            .parse("plan", &args)
+            .map(Value::Object)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn comma_separated_scalars() {
+        assert_eq!(
+            parse(&["--detectors", "det1,det2", "det3"]),
+            Ok(json!({"detectors": ["det1", "det2", "det3"]}))
+        );
+        assert_eq!(parse(&["--shape", "3,4"]), Ok(json!({"shape": [3, 4]})));
+    }
+
+    #[test]
+    fn bracketed_lists_are_not_split() {
+        assert_eq!(parse(&["--shape", "(3, 4)"]), Ok(json!({"shape": [3, 4]})));
+        assert_eq!(parse(&["--shape", "[3,4]"]), Ok(json!({"shape": [3, 4]})));
+        assert_eq!(
+            parse(&["--detectors", "['a,b', 'c']"]),
+            Ok(json!({"detectors": ["a,b", "c"]}))
+        );
+    }
+
+    #[test]
+    fn lists_of_lists_take_one_list_per_value() {
+        assert_eq!(
+            parse(&["--positions", "[1,2]", "(3, 4)"]),
+            Ok(json!({"positions": [[1, 2], [3, 4]]}))
+        );
+    }
+
+    #[test]
+    fn unions_prefer_a_single_value() {
+        assert_eq!(parse(&["--delay", "0.5"]), Ok(json!({"delay": 0.5})));
+        assert_eq!(
+            parse(&["--delay", "0.5,1"]),
+            Ok(json!({"delay": [0.5, 1.0]}))
+        );
+    }
+
+    #[test]
+    fn json_is_passed_whole() {
+        assert_eq!(
+            parse(&["--metadata", r#"{"a": 1, "b": [2, 3]}"#]),
+            Ok(json!({"metadata": {"a": 1, "b": [2, 3]}}))
+        );
+    }
+
+    #[test]
+    fn invalid_item() {
+        assert!(parse(&["--shape", "3,four"]).is_err());
+    }
+}
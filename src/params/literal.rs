@@ -0,0 +1,552 @@
+use std::fmt::Display;
+
+use serde_json::{Map, Number, Value};
+
+/// Parse parameters written as a python literal (or JSON)
+///
+/// This accepts the subset of python's literal syntax that can be represented
+/// as JSON: dicts with string keys, lists, tuples and sets (which all become
+/// arrays), single or double quoted strings, numbers, `True`, `False` and
+/// `None`. JSON's `true`, `false` and `null` are also accepted so any JSON is
+/// valid, and trailing commas are allowed everywhere python allows them.
+///
+/// Input that is valid JSON is parsed as JSON so that its escapes (eg `\/`)
+/// keep their JSON meaning where it differs from python's.
+pub fn parse(input: &str) -> Result<Value, SyntaxError> {
+    if let Ok(value) = serde_json::from_str(input) {
+        return Ok(value);
+    }
+    let mut parser = Parser {
+        input,
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(parser.error_at(
+            parser.pos,
+            format!("unexpected '{c}' after the end of the value"),
+        )),
+    }
+}
+
+/// A problem with the syntax of a literal, displayed with the line it was on
+/// and a caret pointing at the problem
+#[derive(Debug)]
+pub struct SyntaxError {
+    message: String,
+    line: String,
+    /// Line number (from 1) - only shown for input spanning multiple lines
+    line_no: Option<usize>,
+    /// Column in characters (from 0)
+    column: usize,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(line_no) = self.line_no {
+            write!(f, " (line {line_no})")?;
+        }
+        write!(
+            f,
+            "\n  {}\n  {:>width$}",
+            self.line,
+            "^",
+            width = self.column + 1
+        )
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    /// Byte offset of the next character
+    pos: usize,
+    /// Number of lists, tuples and dicts the parser is currently inside
+    depth: usize,
+}
+
+/// How deeply lists, tuples and dicts can be nested (the same limit as
+/// serde_json) so that pathological input is an error rather than a stack
+/// overflow
+const MAX_DEPTH: usize = 128;
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skip whitespace and comments
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn error_at(&self, pos: usize, message: impl Into<String>) -> SyntaxError {
+        let start = self.input[..pos].rfind('\n').map_or(0, |nl| nl + 1);
+        let end = self.input[pos..]
+            .find('\n')
+            .map_or(self.input.len(), |nl| pos + nl);
+        SyntaxError {
+            message: message.into(),
+            line: self.input[start..end].to_owned(),
+            line_no: self
+                .input
+                .contains('\n')
+                .then(|| self.input[..pos].matches('\n').count() + 1),
+            column: self.input[start..pos].chars().count(),
+        }
+    }
+
+    /// An error for whatever is at the current position
+    fn unexpected(&self, expected: &str) -> SyntaxError {
+        match self.peek() {
+            Some(c) => self.error_at(self.pos, format!("expected {expected} but found '{c}'")),
+            None => self.error_at(self.pos, format!("expected {expected} but the input ended")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, SyntaxError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(open @ ('{' | '[' | '(')) => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error_at(
+                        self.pos,
+                        format!("values are nested more than {MAX_DEPTH} levels deep"),
+                    ));
+                }
+                self.depth += 1;
+                let value = match open {
+                    '{' => self.dict(),
+                    '[' => self
+                        .sequence('[', ']')
+                        .map(|(items, _)| Value::Array(items)),
+                    _ => self.tuple(),
+                };
+                self.depth -= 1;
+                value
+            }
+            Some(quote @ ('\'' | '"')) => self.string(quote).map(Value::String),
+            Some(c) if c.is_ascii_digit() || matches!(c, '-' | '+' | '.') => self.number(),
+            Some(c) if c.is_alphabetic() || c == '_' => self.name(),
+            _ => Err(self.unexpected("a value")),
+        }
+    }
+
+    /// Comma separated values up to the closing delimiter, returning whether
+    /// there was a trailing comma
+    fn sequence(&mut self, open: char, close: char) -> Result<(Vec<Value>, bool), SyntaxError> {
+        self.expect(open)?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(close) {
+                self.bump();
+                return Ok((items, true));
+            }
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some(c) if c == close => {
+                    self.bump();
+                    return Ok((items, false));
+                }
+                _ => return Err(self.unexpected(&format!("',' or '{close}'"))),
+            }
+        }
+    }
+
+    /// A tuple, or a value that is only in parentheses for grouping
+    fn tuple(&mut self) -> Result<Value, SyntaxError> {
+        let (mut items, trailing_comma) = self.sequence('(', ')')?;
+        match (items.len(), trailing_comma) {
+            (1, false) => Ok(items.remove(0)),
+            _ => Ok(Value::Array(items)),
+        }
+    }
+
+    /// A dict, or a set (which is treated like a list)
+    fn dict(&mut self) -> Result<Value, SyntaxError> {
+        let open = self.pos;
+        self.expect('{')?;
+        let mut entries = Map::new();
+        let mut set_items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some('}') {
+                self.bump();
+                break;
+            }
+            let key_pos = self.pos;
+            let key = self.value()?;
+            self.skip_whitespace();
+            // The first entry decides whether this is a dict or a set
+            let is_set = match self.peek() {
+                Some(':') if set_items.is_empty() => false,
+                Some(':') => {
+                    return Err(self.error_at(self.pos, "unexpected ':' in a set"));
+                }
+                _ if entries.is_empty() => true,
+                _ => return Err(self.unexpected("':'")),
+            };
+            if is_set {
+                set_items.push(key);
+            } else {
+                self.bump();
+                let Value::String(key) = key else {
+                    return Err(self.error_at(key_pos, "dict keys must be strings"));
+                };
+                let value = self.value()?;
+                entries.insert(key, value);
+            }
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some('}') => {
+                    self.bump();
+                    break;
+                }
+                None => return Err(self.error_at(open, "unclosed '{'")),
+                _ => return Err(self.unexpected("',' or '}'")),
+            }
+        }
+        match set_items.is_empty() {
+            true => Ok(Value::Object(entries)),
+            false => Ok(Value::Array(set_items)),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SyntaxError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            _ => Err(self.unexpected(&format!("'{expected}'"))),
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, SyntaxError> {
+        let open = self.pos;
+        self.bump();
+        let mut value = String::new();
+        loop {
+            let escape = self.pos;
+            match self.bump() {
+                None | Some('\n') => return Err(self.error_at(open, "unterminated string")),
+                Some(c) if c == quote => return Ok(value),
+                Some('\\') => match self.bump() {
+                    None => return Err(self.error_at(open, "unterminated string")),
+                    Some('\n') => {}
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('a') => value.push('\x07'),
+                    Some('b') => value.push('\x08'),
+                    Some('f') => value.push('\x0c'),
+                    Some('v') => value.push('\x0b'),
+                    Some(c @ '0'..='7') => value.push(self.octal(c)),
+                    Some('x') => value.push(self.code_point(escape, 2)?),
+                    Some('u') => value.push(self.utf16(escape)?),
+                    Some('U') => value.push(self.code_point(escape, 8)?),
+                    Some(c @ ('\\' | '\'' | '"')) => value.push(c),
+                    // Python keeps unrecognised escapes as they are
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                },
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    /// The character given by a `\x` or `\U` escape with `digits` hex digits
+    fn code_point(&mut self, escape: usize, digits: usize) -> Result<char, SyntaxError> {
+        self.hex(digits)
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error_at(escape, "invalid escape sequence"))
+    }
+
+    /// The character given by a `\u` escape, which may be the first half of
+    /// a surrogate pair (`\ud83d\ude00`) as written by JSON encoders
+    fn utf16(&mut self, escape: usize) -> Result<char, SyntaxError> {
+        let invalid = |parser: &Self| parser.error_at(escape, "invalid escape sequence");
+        let high = self.hex(4).ok_or_else(|| invalid(self))?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| invalid(self));
+        }
+        if !self.input[self.pos..].starts_with("\\u") {
+            return Err(invalid(self));
+        }
+        self.pos += 2;
+        match self.hex(4) {
+            Some(low @ 0xDC00..0xE000) => {
+                char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+                    .ok_or_else(|| invalid(self))
+            }
+            _ => Err(invalid(self)),
+        }
+    }
+
+    /// Consume exactly `digits` hex digits
+    fn hex(&mut self, digits: usize) -> Option<u32> {
+        let hex = self.input[self.pos..]
+            .chars()
+            .take(digits)
+            .collect::<String>();
+        if hex.len() != digits || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        self.pos += hex.len();
+        u32::from_str_radix(&hex, 16).ok()
+    }
+
+    /// The character given by an octal escape (`\0` to `\777`) starting with
+    /// `first`
+    fn octal(&mut self, first: char) -> char {
+        let mut code = first.to_digit(8).unwrap_or_default();
+        for _ in 0..2 {
+            match self.peek().and_then(|c| c.to_digit(8)) {
+                Some(digit) => {
+                    code = code * 8 + digit;
+                    self.bump();
+                }
+                None => break,
+            }
+        }
+        // At most 0o777 so always a valid character
+        char::from_u32(code).unwrap_or_default()
+    }
+
+    fn number(&mut self) -> Result<Value, SyntaxError> {
+        let start = self.pos;
+        if matches!(self.peek(), Some('-' | '+')) {
+            self.bump();
+        }
+        let mut previous = ' ';
+        while let Some(c) = self.peek() {
+            let exponent_sign = matches!(c, '-' | '+') && matches!(previous, 'e' | 'E');
+            if !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.') || exponent_sign) {
+                break;
+            }
+            previous = c;
+            self.bump();
+        }
+        let text = self.input[start..self.pos].replace('_', "");
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.trim_start_matches('+')),
+        };
+        let radix = match digits.get(..2).map(str::to_ascii_lowercase).as_deref() {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+        let number = match radix {
+            Some(radix) => i64::from_str_radix(&digits[2..], radix)
+                .ok()
+                .map(|n| Number::from(if negative { -n } else { n })),
+            None if digits.contains(['.', 'e', 'E']) => {
+                text.parse::<f64>().ok().and_then(Number::from_f64)
+            }
+            None => text.parse::<i64>().ok().map(Number::from),
+        };
+        number.map(Value::Number).ok_or_else(|| {
+            self.error_at(
+                start,
+                format!("invalid number '{}'", &self.input[start..self.pos]),
+            )
+        })
+    }
+
+    fn name(&mut self) -> Result<Value, SyntaxError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.bump();
+        }
+        match &self.input[start..self.pos] {
+            "True" | "true" => Ok(Value::Bool(true)),
+            "False" | "false" => Ok(Value::Bool(false)),
+            "None" | "null" => Ok(Value::Null),
+            name => Err(self.error_at(
+                start,
+                format!("unknown name '{name}' (strings need to be quoted)"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{MAX_DEPTH, parse};
+
+    fn string(literal: &str) -> String {
+        match parse(literal) {
+            Ok(Value::String(s)) => s,
+            other => panic!("{literal} did not parse to a string: {other:?}"),
+        }
+    }
+
+    fn error(input: &str) -> String {
+        match parse(input) {
+            Err(e) => e.to_string(),
+            Ok(value) => panic!("{input} parsed as {value}"),
+        }
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            parse(r#"{"a": [1, 2.5, "x"], "b": {"c": null}, "d": true}"#).ok(),
+            Some(json!({"a": [1, 2.5, "x"], "b": {"c": null}, "d": true}))
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(string("'single'"), "single");
+        assert_eq!(string(r#""double""#), "double");
+        assert_eq!(string(r#"'it"s'"#), "it\"s");
+        assert_eq!(string(r#""it's""#), "it's");
+        assert_eq!(string(r"'it\'s'"), "it's");
+    }
+
+    #[test]
+    fn python_names() {
+        assert_eq!(
+            parse("[True, False, None, true, false, null]").ok(),
+            Some(json!([true, false, null, true, false, null]))
+        );
+    }
+
+    #[test]
+    fn tuples_and_sets() {
+        assert_eq!(parse("(1, 2)").ok(), Some(json!([1, 2])));
+        assert_eq!(parse("(1,)").ok(), Some(json!([1])));
+        assert_eq!(parse("(1)").ok(), Some(json!(1)));
+        assert_eq!(parse("()").ok(), Some(json!([])));
+        assert_eq!(parse("{'a', 'b'}").ok(), Some(json!(["a", "b"])));
+    }
+
+    #[test]
+    fn trailing_commas() {
+        assert_eq!(
+            parse("{'a': [1, 2,], 'b': (3, 4,),}").ok(),
+            Some(json!({"a": [1, 2], "b": [3, 4]}))
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            parse("[1_000, -2, +3, 0x1f, 0o17, 0b11, 1.5e3, .5]").ok(),
+            Some(json!([1000, -2, 3, 31, 15, 3, 1500.0, 0.5]))
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(string(r"'\n\t\r'"), "\n\t\r");
+        assert_eq!(string(r"'\a\b\f\v'"), "\x07\x08\x0c\x0b");
+        assert_eq!(string(r#"'\\ \' \"'"#), "\\ ' \"");
+        assert_eq!(string(r"'\0'"), "\0");
+        assert_eq!(string(r"'\101\60x'"), "A0x");
+        assert_eq!(string(r"'\x41'"), "A");
+        assert_eq!(string(r"'\u00e9'"), "é");
+        assert_eq!(string(r"'\U0001F600'"), "😀");
+        assert_eq!(string(r"'\ud83d\ude00'"), "😀");
+        assert_eq!(string("'a\\\nb'"), "ab");
+        // Unknown escapes are kept, as python does
+        assert_eq!(string(r"'\d \/'"), r"\d \/");
+    }
+
+    #[test]
+    fn json_escapes() {
+        assert_eq!(string(r#""\/""#), "/");
+        assert_eq!(string(r#""\b\f""#), "\x08\x0c");
+        assert_eq!(string(r#""\ud83d\ude00""#), "😀");
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        assert!(error(r"'\x4'").starts_with("invalid escape sequence"));
+        assert!(error(r"'\ud83d'").starts_with("invalid escape sequence"));
+        assert!(error(r"'\ude00'").starts_with("invalid escape sequence"));
+        assert!(error(r"'\ud83dA'").starts_with("invalid escape sequence"));
+        assert!(error(r"'\U00110000'").starts_with("invalid escape sequence"));
+    }
+
+    #[test]
+    fn caret_position() {
+        assert_eq!(
+            error("{'a': tru}"),
+            "unknown name 'tru' (strings need to be quoted)\n  {'a': tru}\n        ^"
+        );
+        assert_eq!(
+            error("[1, 2 3]"),
+            "expected ',' or ']' but found '3'\n  [1, 2 3]\n        ^"
+        );
+        assert_eq!(
+            error("{'é': 'unterminated}"),
+            "unterminated string\n  {'é': 'unterminated}\n        ^"
+        );
+        assert_eq!(
+            error("[1]]"),
+            "unexpected ']' after the end of the value\n  [1]]\n     ^"
+        );
+    }
+
+    #[test]
+    fn caret_position_multiline() {
+        assert_eq!(
+            error("{\n  'a': 1,\n  'b': x,\n}"),
+            "unknown name 'x' (strings need to be quoted) (line 3)\n    'b': x,\n         ^"
+        );
+    }
+
+    #[test]
+    fn nesting_depth() {
+        // None keeps serde_json from parsing it
+        let nested = |depth| format!("{}None{}", "[".repeat(depth), "]".repeat(depth));
+        assert_eq!(
+            parse(&nested(MAX_DEPTH))
+                .expect("nesting up to the limit")
+                .to_string(),
+            nested(MAX_DEPTH).replace("None", "null")
+        );
+        assert_eq!(
+            error(&nested(MAX_DEPTH + 1)).lines().next(),
+            Some("values are nested more than 128 levels deep")
+        );
+        assert!(error(&"[{(".repeat(100_000)).starts_with("values are nested more than 128"));
+    }
+}
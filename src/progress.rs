@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::messages::data_model::format_number;
+use crate::messages::{ProgressEvent, StatusView};
+
+/// Resolution of the bars - percentages are mapped onto 0..BAR_LENGTH
+const BAR_LENGTH: u64 = 1000;
+const TEMPLATE: &str = "{prefix:.bold} [{bar:40.cyan/blue}] {percent:>3}% {msg}";
+
+/// Handle for printing lines to stdout without disturbing any progress bars
+#[derive(Clone, Default)]
+pub struct Printer(MultiProgress);
+
+impl Printer {
+    /// Print a line above any bars without them being overwritten or duplicated
+    pub fn println(&self, line: impl Display) {
+        self.0.suspend(|| println!("{line}"));
+    }
+
+    /// Print a line to stderr above any bars
+    pub fn eprintln(&self, line: impl Display) {
+        self.0.suspend(|| eprintln!("{line}"));
+    }
+}
+
+/// Terminal progress bars for the statuses being watched by a running plan
+///
+/// One bar is shown per status (keyed by its display name) and removed once
+/// the status reports that it is done.
+pub struct ProgressBars {
+    multi: MultiProgress,
+    bars: HashMap<String, ProgressBar>,
+    style: ProgressStyle,
+}
+
+impl ProgressBars {
+    pub fn new(printer: &Printer) -> Self {
+        Self {
+            multi: printer.0.clone(),
+            bars: HashMap::new(),
+            style: ProgressStyle::with_template(TEMPLATE)
+                .expect("Progress template is valid")
+                .progress_chars("=> "),
+        }
+    }
+
+    pub fn update(&mut self, event: &ProgressEvent) {
+        for status in event.statuses.values() {
+            if status.done {
+                if let Some(bar) = self.bars.remove(&status.display_name) {
+                    bar.finish_and_clear();
+                    self.multi.remove(&bar);
+                }
+                continue;
+            }
+            let bar = self
+                .bars
+                .entry(status.display_name.clone())
+                .or_insert_with(|| {
+                    self.multi.add(
+                        ProgressBar::new(BAR_LENGTH)
+                            .with_style(self.style.clone())
+                            .with_prefix(status.display_name.clone()),
+                    )
+                });
+            if let Some(fraction) = status.percentage {
+                bar.set_position((fraction.clamp(0.0, 1.0) * BAR_LENGTH as f64) as u64);
+            }
+            bar.set_message(describe(status));
+        }
+    }
+
+    /// Remove any bars that were not marked as done
+    pub fn clear(&mut self) {
+        for (_, bar) in self.bars.drain() {
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+    }
+}
+
+impl Drop for ProgressBars {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// The values and timings of a status, eg `1.50/3.00 mm  elapsed 2s  ETA 2s`
+fn describe(status: &StatusView) -> String {
+    let fmt = |value: f64| format_number(value, status.precision);
+    let mut desc = match (status.current, status.target) {
+        (Some(current), Some(target)) => format!("{}/{}", fmt(current), fmt(target)),
+        (Some(current), None) => fmt(current),
+        (None, _) => String::new(),
+    };
+    if let Some(unit) = &status.unit
+        && !desc.is_empty()
+    {
+        desc.push(' ');
+        desc.push_str(unit);
+    }
+    if let Some(elapsed) = status.time_elapsed {
+        desc.push_str(&format!("  elapsed {}", duration(elapsed)));
+    }
+    if let Some(remaining) = status.time_remaining {
+        desc.push_str(&format!("  ETA {}", duration(remaining)));
+    }
+    desc
+}
+
+/// Format a number of seconds as eg `1h02m03s`, `2m03s` or `3s`
+fn duration(secs: f64) -> String {
+    let secs = secs.max(0.0).round() as u64;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, s) => format!("{h}h{m:02}m{s:02}s"),
+    }
+}
@@ -0,0 +1,300 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use clap::{Command, CommandFactory, Parser};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Context, Editor, ExternalPrinter, Helper};
+use tokio::signal;
+
+use crate::Client;
+use crate::cli::CliArgs;
+use crate::complete::{Entry, Names};
+use crate::config;
+use crate::entities::WorkerState;
+use crate::error::{BcliError, Result};
+use crate::events::{EventStream, Received, SharedEvents};
+use crate::messages::Message;
+
+/// A line entered in the shell - the same commands as bcli itself without the
+/// connection options
+#[derive(Debug, Parser)]
+#[command(name = "bcli", no_binary_name = true, disable_version_flag = true)]
+struct Line {
+    #[command(subcommand)]
+    command: CliArgs,
+}
+
+/// Names from the server that can be completed, refreshed after each command
+#[derive(Default)]
+struct Known {
+    plans: Vec<Entry>,
+    devices: Vec<Entry>,
+    tasks: Vec<Entry>,
+}
+
+/// Run commands entered interactively until the input ends or `exit` is entered
+///
+/// All commands share one client and one subscription to the event bus. The
+/// prompt shows the state of the worker and changes of state are printed while
+/// waiting for input. Ctrl-C interrupts the current command rather than the
+/// session.
+pub async fn run(mut client: Client) -> Result<()> {
+    match SharedEvents::connect(&client.server).await {
+        Ok(events) => client.events = Some(events),
+        Err(e) => eprintln!("Warning: {e}\nWorker state changes will not be shown"),
+    }
+    let state = Arc::new(Mutex::new(client.worker_state().await.ok()));
+    let known = Arc::new(Mutex::new(Known::default()));
+    refresh(&client, &known);
+
+    let editor_config = rustyline::Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::with_config(editor_config)
+        .map_err(terminal_error)?;
+    editor.set_helper(Some(ShellHelper {
+        known: known.clone(),
+        commands: Line::command(),
+    }));
+    let history = config::state_dir().map(|dir| dir.join("history"));
+    if let Some(path) = &history {
+        // There won't be any history the first time
+        let _ = editor.load_history(path);
+    }
+
+    let at_prompt = Arc::new(AtomicBool::new(false));
+    if let Some(events) = &client.events {
+        let printer = editor.create_external_printer().ok();
+        tokio::spawn(watch_state(
+            events.stream(),
+            state.clone(),
+            at_prompt.clone(),
+            printer,
+        ));
+    }
+
+    loop {
+        let prompt = match *lock(&state) {
+            Some(state) => format!("bcli [{state}]> "),
+            None => "bcli> ".into(),
+        };
+        at_prompt.store(true, Ordering::Relaxed);
+        let line = editor.readline(&prompt);
+        at_prompt.store(false, Ordering::Relaxed);
+        let line = match line {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(terminal_error(e)),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        if matches!(line, "exit" | "quit") {
+            break;
+        }
+        let Some(words) = shlex::split(line) else {
+            eprintln!("Error: unterminated quote");
+            continue;
+        };
+        let command = match Line::try_parse_from(words) {
+            Ok(line) => line.command,
+            Err(e) => {
+                let _ = e.print();
+                continue;
+            }
+        };
+        tokio::select! {
+            result = client.execute(command) => {
+                if let Err(e) = result {
+                    eprintln!("{e}");
+                }
+            }
+            _ = signal::ctrl_c() => eprintln!("Interrupted"),
+        }
+        refresh(&client, &known);
+        if client.events.is_none() {
+            *lock(&state) = client.worker_state().await.ok();
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Couldn't save history to {}: {e}", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn terminal_error(e: ReadlineError) -> BcliError {
+    BcliError::Io("<terminal>".into(), io::Error::other(e))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Fetch the names used for completion in the background so the prompt isn't
+/// held up by a slow server
+fn refresh(client: &Client, known: &Arc<Mutex<Known>>) {
+    let client = client.clone();
+    let known = known.clone();
+    tokio::spawn(async move {
+        let (plans, devices, tasks) = tokio::join!(
+            Names::Plans.fetch(&client),
+            Names::Devices.fetch(&client),
+            Names::Tasks.fetch(&client),
+        );
+        let mut known = lock(&known);
+        if let Ok(plans) = plans {
+            known.plans = plans;
+        }
+        if let Ok(devices) = devices {
+            known.devices = devices;
+        }
+        if let Ok(tasks) = tasks {
+            known.tasks = tasks;
+        }
+    });
+}
+
+/// Keep track of the worker's state, printing any changes and notices about
+/// the event stream while waiting for input (commands print the events and
+/// notices they are interested in themselves)
+async fn watch_state(
+    mut messages: EventStream,
+    state: Arc<Mutex<Option<WorkerState>>>,
+    at_prompt: Arc<AtomicBool>,
+    mut printer: Option<impl ExternalPrinter>,
+) {
+    while let Some(received) = messages.next().await {
+        let line = match received {
+            Received::Message(Message::Worker(event)) => {
+                let previous = lock(&state).replace(event.state);
+                if previous == Some(event.state) {
+                    continue;
+                }
+                format!("Worker state: {}", event.state)
+            }
+            Received::Message(_) => continue,
+            Received::Notice(notice) => notice,
+        };
+        if at_prompt.load(Ordering::Relaxed)
+            && let Some(printer) = &mut printer
+        {
+            let _ = printer.print(line);
+        }
+    }
+}
+
+/// Completes commands, their options and the names of plans, devices and tasks
+struct ShellHelper {
+    known: Arc<Mutex<Known>>,
+    commands: Command,
+}
+
+impl ShellHelper {
+    /// Everything that could be entered at the current position, with a
+    /// description if there is one
+    fn candidates(&self, words: &[&str], current: &str) -> Vec<(String, Option<String>)> {
+        let mut cmd = &self.commands;
+        let mut positional = 0;
+        for word in words {
+            match cmd.find_subcommand(word) {
+                Some(sub) => {
+                    cmd = sub;
+                    positional = 0;
+                }
+                None if !word.starts_with('-') => positional += 1,
+                None => {}
+            }
+        }
+        if current.starts_with('-') {
+            return cmd
+                .get_arguments()
+                .filter(|arg| !arg.is_hide_set())
+                .filter_map(|arg| {
+                    let long = arg.get_long()?;
+                    Some((format!("--{long}"), arg.get_help().map(|h| h.to_string())))
+                })
+                .collect();
+        }
+        if cmd.has_subcommands() && positional == 0 {
+            let mut commands = cmd
+                .get_subcommands()
+                .map(|sub| {
+                    let about = sub.get_about().map(|about| about.to_string());
+                    (sub.get_name().to_owned(), about)
+                })
+                .collect::<Vec<_>>();
+            if words.is_empty() {
+                commands.push(("exit".into(), Some("Leave the shell".into())));
+            }
+            return commands;
+        }
+        let Some(arg) = cmd.get_positionals().nth(positional) else {
+            return vec![];
+        };
+        let known = lock(&self.known);
+        let names = match (cmd.get_name(), arg.get_id().as_str()) {
+            ("run" | "plans", "name") => &known.plans,
+            ("devices", "name") => &known.devices,
+            (_, "task_id") => &known.tasks,
+            _ => return vec![],
+        };
+        names
+            .iter()
+            .map(|entry| (entry.value.clone(), entry.help.clone()))
+            .collect()
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |idx| idx + 1);
+        let current = &line[start..];
+        let words = line[..start].split_whitespace().collect::<Vec<_>>();
+        let candidates = self
+            .candidates(&words, current)
+            .into_iter()
+            .filter(|(value, _)| value.starts_with(current))
+            .map(|(value, help)| Pair {
+                display: match help {
+                    Some(help) => format!("{value}  {}", help.lines().next().unwrap_or_default()),
+                    None => value.clone(),
+                },
+                replacement: value,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
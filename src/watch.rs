@@ -0,0 +1,92 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Local};
+use serde::{Serialize, Serializer};
+
+use crate::entities::{TaskId, WorkerState};
+use crate::messages::WorkerEvent;
+
+/// A change in the state of the worker, as reported by `state --watch`
+#[derive(Debug, Clone, Serialize)]
+pub struct StateChange {
+    #[serde(serialize_with = "rfc3339")]
+    time: DateTime<Local>,
+    state: WorkerState,
+    /// The task being run, if there is one
+    task_id: Option<TaskId>,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Reduces worker events (or polled states) to the changes worth reporting
+///
+/// The worker repeats its state, and the errors of the current task, in every
+/// event so only events where something differs from the previous one are
+/// passed on.
+#[derive(Debug, Default)]
+pub struct StateWatch {
+    last: Option<StateChange>,
+}
+
+impl StateWatch {
+    pub fn event(&mut self, event: &WorkerEvent) -> Option<StateChange> {
+        let task_id = event
+            .task_status
+            .as_ref()
+            .filter(|status| !status.task_complete)
+            .map(|status| status.task_id);
+        self.update(event.state, task_id, &event.errors, &event.warnings)
+    }
+
+    pub fn update(
+        &mut self,
+        state: WorkerState,
+        task_id: Option<TaskId>,
+        errors: &[String],
+        warnings: &[String],
+    ) -> Option<StateChange> {
+        let unchanged = self.last.as_ref().is_some_and(|last| {
+            last.state == state
+                && last.task_id == task_id
+                && last.errors == errors
+                && last.warnings == warnings
+        });
+        if unchanged {
+            return None;
+        }
+        let change = StateChange {
+            time: Local::now(),
+            state,
+            task_id,
+            errors: errors.to_vec(),
+            warnings: warnings.to_vec(),
+        };
+        self.last = Some(change.clone());
+        Some(change)
+    }
+}
+
+impl Display for StateChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}  {:<10}",
+            self.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.state
+        )?;
+        if let Some(task_id) = self.task_id {
+            write!(f, "  task {task_id}")?;
+        }
+        for error in &self.errors {
+            write!(f, "\n  Error: {error}")?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\n  Warning: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+fn rfc3339<S: Serializer>(time: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.to_rfc3339())
+}